@@ -0,0 +1,496 @@
+//! Command-line tool to list ETFs and query their holdings without running the web server.
+//!
+//! Exit codes: 0 on success, 1 on bad options or output errors, 2 on usage errors, 3 if an ETF
+//! isn't supported and 4 if fetching data from a fund manager failed.
+
+use clap::{Parser, Subcommand};
+use etf_holdings_lib::{
+    export, overlap, ETFHoldings, ETFHoldingsOptions, ETFListItem, Error as ETFErr, ExportFormat,
+    ETF,
+};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing_subscriber::EnvFilter;
+
+mod output;
+mod replay;
+mod types;
+use output::{optional_number, write_output};
+use replay::Source;
+use types::{to_cli_error, CliError, CliResult, OutputFormat, Table};
+
+/// Query ETF holdings straight from the fund managers' websites.
+#[derive(Parser)]
+#[command(name = "etf-holdings", version, about)]
+struct Cli {
+    /// Output format
+    #[arg(long, short, value_enum, global = true, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+    /// JSON file with extra ticker mappings (see `TickerMapping`)
+    #[arg(long, global = true, env = "ETF_TICKER_MAPPING")]
+    ticker_mapping: Option<PathBuf>,
+    /// Fail on bad rows in holdings files instead of skipping them
+    #[arg(long, global = true)]
+    strict: bool,
+    /// Seconds to wait for each request to a fund manager
+    #[arg(long, global = true)]
+    timeout: Option<u64>,
+    /// Save every fetched ETF as DIR/<TICKER>.json
+    #[arg(long, global = true, value_name = "DIR")]
+    record: Option<PathBuf>,
+    /// Read ETFs saved with --record from DIR instead of fetching them
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "record")]
+    replay: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List all supported ETFs
+    List,
+    /// Find supported ETFs by ticker or name
+    Search { query: String },
+    /// Show the holdings of an ETF
+    Holdings { ticker: String },
+    /// Compare the holdings of two or more ETFs
+    Overlap {
+        #[arg(num_args = 2.., required = true)]
+        tickers: Vec<String>,
+    },
+    /// Write the holdings of one or more ETFs to a file, `--format` doesn't apply
+    Export {
+        #[arg(required = true)]
+        tickers: Vec<String>,
+        /// File to write to, defaults to stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// csv, jsonl, parquet or xlsx, defaults to the output file's extension (csv without one)
+        #[arg(long)]
+        to: Option<ExportFormat>,
+    },
+}
+
+/// Table of listed ETFs
+fn etf_list_table(etfs: &[ETFListItem]) -> Table {
+    Table {
+        headers: vec!["Ticker".to_string(), "Name".to_string()],
+        rows: etfs
+            .iter()
+            .map(|etf| vec![etf.ticker.clone(), etf.name.clone()])
+            .collect(),
+    }
+}
+
+/// Table of the holdings of one or more ETFs
+fn holdings_table(etfs: &[ETF]) -> Table {
+    let headers = [
+        "ETF",
+        "Ticker",
+        "Name",
+        "Asset Class",
+        "Sector",
+        "Weight",
+        "Market Value",
+        "Shares",
+        "Price",
+        "Location",
+        "Exchange",
+        "Currency",
+    ];
+    let mut rows = Vec::new();
+    for etf in etfs {
+        for holding in &etf.holdings {
+            rows.push(vec![
+                etf.ticker.clone(),
+                holding.ticker.clone(),
+                holding.name.clone(),
+                holding.asset_class.clone(),
+                holding.sector.clone().unwrap_or_default(),
+                holding.weight.to_string(),
+                holding.market_value.to_string(),
+                optional_number(holding.shares),
+                optional_number(holding.price),
+                holding.location.clone(),
+                holding.exchange.clone(),
+                holding.currency.clone(),
+            ]);
+        }
+    }
+    Table {
+        headers: headers.iter().map(|h| h.to_string()).collect(),
+        rows,
+    }
+}
+
+/// Fetch the details of a few ETFs, in the given order.
+async fn fetch_etfs(source: &Source, tickers: &[String]) -> CliResult<Vec<ETF>> {
+    let mut etfs = Vec::new();
+    for ticker in tickers {
+        let etf = source.etf_details(ticker).await?;
+        if !etf.warnings.is_empty() {
+            eprintln!(
+                "{}: {} rows of the holdings file had problems, see --format json.",
+                etf.ticker,
+                etf.warnings.len()
+            );
+        }
+        etfs.push(etf);
+    }
+    Ok(etfs)
+}
+
+/// The export format from `--to`, or else from the extension of the output file. Files with an
+/// extension that isn't a format (e.g. `.json`, exports are JSON lines) are rejected rather than
+/// written as CSV.
+fn export_format(to: Option<ExportFormat>, output: Option<&Path>) -> CliResult<ExportFormat> {
+    if let Some(to) = to {
+        return Ok(to);
+    }
+    let extension = match output.and_then(Path::extension) {
+        Some(extension) => extension.to_string_lossy(),
+        None => return Ok(ExportFormat::Csv),
+    };
+    extension.parse().map_err(|_| {
+        CliError::Generic(format!(
+            "Can't tell the export format from the extension \".{}\", pass --to with one of \
+             csv, jsonl, parquet or xlsx.",
+            extension
+        ))
+    })
+}
+
+/// Set up fetching from the fund managers, or reading a recording with `--replay`.
+async fn source(cli: &Cli) -> CliResult<Source> {
+    if let Some(dir) = &cli.replay {
+        return Ok(Source::Replay(dir.clone()));
+    }
+
+    let options = ETFHoldingsOptions {
+        ticker_mapping_path: cli.ticker_mapping.clone(),
+        lenient_parsing: !cli.strict,
+        request_timeout: cli.timeout.map(Duration::from_secs),
+        ..Default::default()
+    };
+    let etf_holdings = ETFHoldings::with_options(options)
+        .await
+        .map_err(|err| match err {
+            // Only the ticker mapping file or the HTTP client can fail here
+            ETFErr::Generic(msg) => {
+                CliError::Generic(format!("Failed to set up fetching ETFs: {}", msg))
+            }
+            ETFErr::NotFound => CliError::Generic("Ticker mapping file not found.".to_string()),
+        })?;
+    // ETFHoldings skips fund managers it can't reach, so no ETFs means no fund manager worked
+    if etf_holdings.etf_list().await.is_empty() {
+        return Err(CliError::Upstream(
+            "Couldn't fetch the list of ETFs from any fund manager.".to_string(),
+        ));
+    }
+    Ok(Source::Live {
+        etf_holdings: Box::new(etf_holdings),
+        record: cli.record.clone(),
+    })
+}
+
+/// Run a command, writing results to `stdout`.
+async fn run(cli: Cli, mut stdout: impl Write + Send) -> CliResult<()> {
+    let source = source(&cli).await?;
+
+    let format = cli.format;
+    match cli.command {
+        Command::List => {
+            let etf_list = source.etf_list().await?;
+            write_output(&mut stdout, format, &etf_list, &etf_list_table(&etf_list))?;
+        }
+        Command::Search { query } => {
+            let matches = source.search(&query).await?;
+            if matches.is_empty() {
+                return Err(CliError::NotFound(format!("No ETFs match \"{}\".", query)));
+            }
+            write_output(&mut stdout, format, &matches, &etf_list_table(&matches))?;
+        }
+        Command::Holdings { ticker } => {
+            let etfs = fetch_etfs(&source, &[ticker]).await?;
+            write_output(&mut stdout, format, &etfs[0], &holdings_table(&etfs))?;
+        }
+        Command::Overlap { tickers } => {
+            let mut seen = HashSet::new();
+            if let Some(ticker) = tickers.iter().find(|ticker| !seen.insert(ticker.as_str())) {
+                return Err(CliError::Generic(format!(
+                    "{} is listed more than once.",
+                    ticker
+                )));
+            }
+            let etfs = fetch_etfs(&source, &tickers).await?;
+            let overlap = overlap(&etfs);
+
+            let mut headers = vec!["Ticker".to_string(), "Name".to_string()];
+            headers.extend(overlap.tickers.iter().map(|ticker| format!("{} %", ticker)));
+            let rows = overlap
+                .common_holdings
+                .iter()
+                .map(|holding| {
+                    let mut row = vec![holding.ticker.clone(), holding.name.clone()];
+                    row.extend(holding.weights.iter().map(|weight| weight.to_string()));
+                    row
+                })
+                .collect();
+
+            if format == OutputFormat::Table {
+                writeln!(
+                    stdout,
+                    "Weighted overlap: {:.2}%\n",
+                    overlap.weighted_overlap
+                )
+                .map_err(to_cli_error)?;
+            }
+            write_output(stdout, format, &overlap, &Table { headers, rows })?;
+        }
+        Command::Export {
+            tickers,
+            output,
+            to,
+        } => {
+            let to = export_format(to, output.as_deref())?;
+            let etfs = fetch_etfs(&source, &tickers).await?;
+            let result = match output {
+                Some(path) => {
+                    let file = File::create(&path).map_err(|err| {
+                        CliError::Generic(format!("Can't create {}: {}", path.display(), err))
+                    })?;
+                    export(&etfs, to, BufWriter::new(file))
+                }
+                None => export(&etfs, to, stdout),
+            };
+            result.map_err(|err| CliError::Generic(format!("Export failed: {:?}", err)))?;
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    // Library logs go to stderr so they don't mix with the output, warnings only by default
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+    match run(cli, io::stdout()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::error::ErrorKind;
+    use etf_holdings_lib::{
+        vendor_symbols, Holding, TickerMapping, ValidationReport, SCHEMA_VERSION,
+    };
+
+    fn holding(ticker: &str, weight: f64) -> Holding {
+        Holding {
+            ticker: ticker.to_string(),
+            name: format!("{} Name", ticker),
+            asset_class: "Equity".to_string(),
+            sector: None,
+            market_value: weight * 1000.0,
+            weight,
+            notional_value: None,
+            shares: None,
+            price: None,
+            location: "United States".to_string(),
+            exchange: "NASDAQ".to_string(),
+            currency: "USD".to_string(),
+            fx_rate: None,
+            market_currency: "USD".to_string(),
+            symbols: vendor_symbols(&TickerMapping::default(), ticker, "NASDAQ"),
+        }
+    }
+
+    fn etf(ticker: &str, holdings: &[(&str, f64)]) -> ETF {
+        ETF {
+            schema_version: SCHEMA_VERSION,
+            ticker: ticker.to_string(),
+            name: format!("iShares {} ETF", ticker),
+            currency: "USD".to_string(),
+            last_update: "Oct 15, 2021".to_string(),
+            outstanding_shares: 100.0,
+            holdings: holdings.iter().map(|(t, w)| holding(t, *w)).collect(),
+            unresolved_holdings: Vec::new(),
+            warnings: Vec::new(),
+            validation: ValidationReport::default(),
+        }
+    }
+
+    /// A recording of two ETFs in a fresh directory
+    fn recording(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("etf-holdings-cli-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for etf in [
+            etf("AAA", &[("AAPL", 60.0), ("MSFT", 40.0)]),
+            etf("BBB", &[("AAPL", 100.0)]),
+        ] {
+            let file = File::create(dir.join(format!("{}.json", etf.ticker))).unwrap();
+            serde_json::to_writer(file, &etf).unwrap();
+        }
+        dir
+    }
+
+    /// Run a command against a recording, returns the output or the exit code
+    async fn run_replay(dir: &Path, args: &[&str]) -> Result<String, u8> {
+        let replay = dir.to_str().unwrap();
+        let mut full_args = vec!["etf-holdings", "--replay", replay];
+        full_args.extend(args);
+        let cli = Cli::try_parse_from(full_args).unwrap();
+        let mut output = Vec::new();
+        match run(cli, &mut output).await {
+            Ok(()) => Ok(String::from_utf8(output).unwrap()),
+            Err(err) => Err(err.exit_code()),
+        }
+    }
+
+    #[test]
+    fn global_options_go_before_or_after_the_command() {
+        let cli = Cli::try_parse_from(["etf-holdings", "holdings", "AAA", "-f", "json"]).unwrap();
+        assert_eq!(cli.format, OutputFormat::Json);
+        let cli =
+            Cli::try_parse_from(["etf-holdings", "--strict", "--timeout", "5", "list"]).unwrap();
+        assert!(cli.strict);
+        assert_eq!(cli.timeout, Some(5));
+        assert_eq!(cli.format, OutputFormat::Table);
+    }
+
+    #[test]
+    fn usage_errors_exit_with_2() {
+        for (args, kind) in [
+            (
+                vec!["etf-holdings", "--record", "a", "--replay", "b", "list"],
+                ErrorKind::ArgumentConflict,
+            ),
+            (
+                vec!["etf-holdings", "overlap", "AAA"],
+                ErrorKind::TooFewValues,
+            ),
+            (
+                vec!["etf-holdings", "export"],
+                ErrorKind::MissingRequiredArgument,
+            ),
+            (
+                vec!["etf-holdings", "export", "AAA", "--to", "json"],
+                ErrorKind::ValueValidation,
+            ),
+            (
+                vec!["etf-holdings", "list", "--format", "yaml"],
+                ErrorKind::InvalidValue,
+            ),
+        ] {
+            let err = Cli::try_parse_from(&args).err().unwrap();
+            assert_eq!(err.kind(), kind, "{:?}", args);
+            assert_eq!(err.exit_code(), 2);
+        }
+    }
+
+    #[test]
+    fn export_format_comes_from_to_or_the_extension() {
+        let format = |to, output: Option<&str>| export_format(to, output.map(Path::new));
+        assert_eq!(format(None, None).unwrap(), ExportFormat::Csv);
+        assert_eq!(format(None, Some("holdings")).unwrap(), ExportFormat::Csv);
+        assert_eq!(
+            format(None, Some("holdings.parquet")).unwrap(),
+            ExportFormat::Parquet
+        );
+        assert_eq!(
+            format(None, Some("holdings.jsonl")).unwrap(),
+            ExportFormat::JsonLines
+        );
+        assert_eq!(
+            format(Some(ExportFormat::Xlsx), Some("holdings.csv")).unwrap(),
+            ExportFormat::Xlsx
+        );
+        for output in ["holdings.json", "holdings.txt"] {
+            let err = format(None, Some(output)).unwrap_err();
+            assert_eq!(err.exit_code(), 1);
+            assert!(err.to_string().contains("--to"), "{}", err);
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_every_output_format() {
+        let dir = recording("formats");
+
+        let table = run_replay(&dir, &["holdings", "AAA"]).await.unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].starts_with("ETF  Ticker  Name"), "{}", lines[0]);
+        assert_eq!(lines.len(), 3);
+
+        let json = run_replay(&dir, &["holdings", "AAA", "--format", "json"])
+            .await
+            .unwrap();
+        let etf: ETF = serde_json::from_str(&json).unwrap();
+        assert_eq!(etf.holdings.len(), 2);
+
+        let csv = run_replay(&dir, &["list", "--format", "csv"])
+            .await
+            .unwrap();
+        assert_eq!(
+            csv,
+            "Ticker,Name\nAAA,iShares AAA ETF\nBBB,iShares BBB ETF\n"
+        );
+
+        let overlap = run_replay(&dir, &["overlap", "AAA", "BBB"]).await.unwrap();
+        assert!(
+            overlap.starts_with("Weighted overlap: 60.00%"),
+            "{}",
+            overlap
+        );
+
+        let jsonl = run_replay(&dir, &["export", "AAA", "BBB", "--to", "jsonl"])
+            .await
+            .unwrap();
+        assert_eq!(jsonl.lines().count(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn exit_codes() {
+        let dir = recording("exit-codes");
+        assert_eq!(run_replay(&dir, &["holdings", "ZZZ"]).await, Err(3));
+        assert_eq!(run_replay(&dir, &["search", "bond"]).await, Err(3));
+        assert_eq!(run_replay(&dir, &["overlap", "AAA", "AAA"]).await, Err(1));
+
+        let output = dir.join("holdings.json");
+        let output = output.to_str().unwrap();
+        assert_eq!(
+            run_replay(&dir, &["export", "AAA", "--output", output]).await,
+            Err(1)
+        );
+        assert!(!Path::new(output).exists());
+
+        let output = dir.join("holdings.jsonl");
+        let output = output.to_str().unwrap();
+        assert_eq!(
+            run_replay(&dir, &["export", "AAA", "--output", output]).await,
+            Ok(String::new())
+        );
+        let exported = std::fs::read_to_string(output).unwrap();
+        assert_eq!(exported.lines().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+//! Module used for writing results as a table, JSON or CSV.
+
+use serde::Serialize;
+use std::io::Write;
+
+use crate::types::{to_cli_error, CliResult, OutputFormat, Table};
+
+/// Write a result in the given format. Tables and CSV are written from `table`, JSON is
+/// serialized from `data` so it includes every field.
+pub fn write_output<W: Write>(
+    mut writer: W,
+    format: OutputFormat,
+    data: &impl Serialize,
+    table: &Table,
+) -> CliResult<()> {
+    match format {
+        OutputFormat::Table => write_table(&mut writer, table),
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, data).map_err(to_cli_error)?;
+            writeln!(writer).map_err(to_cli_error)
+        }
+        OutputFormat::Csv => write_csv(&mut writer, table),
+    }
+}
+
+/// Write rows as aligned columns, numbers are aligned right.
+fn write_table<W: Write>(writer: &mut W, table: &Table) -> CliResult<()> {
+    let mut widths: Vec<usize> = table.headers.iter().map(|h| h.chars().count()).collect();
+    for row in &table.rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header: Vec<String> = table
+        .headers
+        .iter()
+        .zip(&widths)
+        .map(|(header, width)| format!("{:<width$}", header, width = width))
+        .collect();
+    writeln!(writer, "{}", header.join("  ").trim_end()).map_err(to_cli_error)?;
+    for row in &table.rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| {
+                if cell.parse::<f64>().is_ok() {
+                    format!("{:>width$}", cell, width = width)
+                } else {
+                    format!("{:<width$}", cell, width = width)
+                }
+            })
+            .collect();
+        writeln!(writer, "{}", cells.join("  ").trim_end()).map_err(to_cli_error)?;
+    }
+    Ok(())
+}
+
+/// Write rows as CSV with a header line.
+fn write_csv<W: Write>(writer: &mut W, table: &Table) -> CliResult<()> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    csv_writer
+        .write_record(&table.headers)
+        .map_err(to_cli_error)?;
+    for row in &table.rows {
+        csv_writer.write_record(row).map_err(to_cli_error)?;
+    }
+    csv_writer.flush().map_err(to_cli_error)
+}
+
+/// Format an optional number, empty if missing.
+pub fn optional_number(value: Option<f64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
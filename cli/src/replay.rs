@@ -0,0 +1,163 @@
+//! Record fetched ETFs to a directory and replay them later without network access.
+//!
+//! Every ETF is stored as `<DIR>/<TICKER>.json` in the same format as `--format json`, so a
+//! recording can also be made by hand or with the web server's `/api/etf/<ticker>`.
+
+use etf_holdings_lib::{ETFHoldings, ETFListItem, ETF};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::types::{etf_details_error, CliError, CliResult};
+
+/// Where ETFs come from
+pub enum Source {
+    /// Fetched from the fund managers, and saved to a directory if there is one
+    Live {
+        etf_holdings: Box<ETFHoldings>,
+        record: Option<PathBuf>,
+    },
+    /// Read from a recording, nothing is fetched
+    Replay(PathBuf),
+}
+
+impl Source {
+    /// All supported ETFs, or all recorded ETFs when replaying.
+    pub async fn etf_list(&self) -> CliResult<Vec<ETFListItem>> {
+        match self {
+            Source::Live { etf_holdings, .. } => Ok(etf_holdings.etf_list().await),
+            Source::Replay(dir) => recorded_etf_list(dir),
+        }
+    }
+
+    /// ETFs with the query in their ticker or name, ignoring case.
+    pub async fn search(&self, query: &str) -> CliResult<Vec<ETFListItem>> {
+        match self {
+            Source::Live { etf_holdings, .. } => Ok(etf_holdings.search(query).await),
+            Source::Replay(dir) => {
+                let mut etfs = recorded_etf_list(dir)?;
+                etfs.retain(|etf| etf.matches(query));
+                Ok(etfs)
+            }
+        }
+    }
+
+    /// Details and holdings of an ETF.
+    pub async fn etf_details(&self, ticker: &String) -> CliResult<ETF> {
+        match self {
+            Source::Live {
+                etf_holdings,
+                record,
+            } => {
+                let etf = etf_holdings
+                    .etf_details(ticker)
+                    .await
+                    .map_err(|err| etf_details_error(ticker, err))?;
+                if let Some(dir) = record {
+                    record_etf(dir, &etf)?;
+                }
+                Ok(etf)
+            }
+            Source::Replay(dir) => replay_etf(dir, ticker),
+        }
+    }
+}
+
+/// File of a recorded ETF
+fn etf_path(dir: &Path, ticker: &str) -> PathBuf {
+    dir.join(format!("{}.json", ticker))
+}
+
+/// Save an ETF to the recording in `dir`, replacing an older recording of it.
+fn record_etf(dir: &Path, etf: &ETF) -> CliResult<()> {
+    let path = etf_path(dir, &etf.ticker);
+    let write = || -> Result<(), Box<dyn std::error::Error>> {
+        fs::create_dir_all(dir)?;
+        serde_json::to_writer_pretty(BufWriter::new(File::create(&path)?), etf)?;
+        Ok(())
+    };
+    write().map_err(|err| CliError::Generic(format!("Can't record {}: {}", path.display(), err)))
+}
+
+/// Read a recorded ETF from `dir`.
+fn replay_etf(dir: &Path, ticker: &str) -> CliResult<ETF> {
+    let path = etf_path(dir, ticker);
+    let file = File::open(&path).map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => CliError::NotFound(format!(
+            "ETF {} isn't recorded in {}.",
+            ticker,
+            dir.display()
+        )),
+        _ => CliError::Generic(format!("Can't read {}: {}", path.display(), err)),
+    })?;
+    serde_json::from_reader(BufReader::new(file))
+        .map_err(|err| CliError::Generic(format!("Invalid recording {}: {}", path.display(), err)))
+}
+
+/// Tickers and names of all ETFs recorded in `dir`, sorted by ticker.
+fn recorded_etf_list(dir: &Path) -> CliResult<Vec<ETFListItem>> {
+    let entries = fs::read_dir(dir)
+        .map_err(|err| CliError::Generic(format!("Can't read {}: {}", dir.display(), err)))?;
+    let mut etfs = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|err| CliError::Generic(err.to_string()))?
+            .path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+        let ticker = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(ticker) => ticker,
+            None => continue,
+        };
+        let etf = replay_etf(dir, ticker)?;
+        etfs.push(ETFListItem {
+            ticker: etf.ticker,
+            name: etf.name,
+        });
+    }
+    etfs.sort_by(|a, b| a.ticker.cmp(&b.ticker));
+    Ok(etfs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use etf_holdings_lib::{ValidationReport, SCHEMA_VERSION};
+
+    fn etf(ticker: &str, name: &str) -> ETF {
+        ETF {
+            schema_version: SCHEMA_VERSION,
+            ticker: ticker.to_string(),
+            name: name.to_string(),
+            currency: "USD".to_string(),
+            last_update: "Oct 15, 2021".to_string(),
+            outstanding_shares: 100.0,
+            holdings: Vec::new(),
+            unresolved_holdings: Vec::new(),
+            warnings: Vec::new(),
+            validation: ValidationReport::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_etfs() {
+        let dir = std::env::temp_dir().join(format!("etf-holdings-replay-{}", std::process::id()));
+        record_etf(&dir, &etf("IVV", "iShares Core S&P 500 ETF")).unwrap();
+        record_etf(&dir, &etf("AGG", "iShares Core US Aggregate Bond ETF")).unwrap();
+        fs::write(dir.join("notes.txt"), "not an ETF").unwrap();
+
+        let source = Source::Replay(dir.clone());
+        let tickers = |etfs: Vec<ETFListItem>| -> Vec<String> {
+            etfs.into_iter().map(|etf| etf.ticker).collect()
+        };
+        assert_eq!(tickers(source.etf_list().await.unwrap()), ["AGG", "IVV"]);
+        assert_eq!(tickers(source.search("s&p").await.unwrap()), ["IVV"]);
+        let ivv = source.etf_details(&"IVV".to_string()).await.unwrap();
+        assert_eq!(ivv.name, "iShares Core S&P 500 ETF");
+        let missing = source.etf_details(&"IWB".to_string()).await.unwrap_err();
+        assert_eq!(missing.exit_code(), 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
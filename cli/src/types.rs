@@ -0,0 +1,68 @@
+//! Types used by the command-line tool.
+
+use clap::ValueEnum;
+use etf_holdings_lib::Error as ETFErr;
+use std::fmt::{self, Display};
+
+/// How results are written to the output
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Rows of a result, used for table and CSV output
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Errors of the command-line tool, each with its own exit code.
+#[derive(Debug)]
+pub enum CliError {
+    /// Bad options, or the output couldn't be written (exit code 1)
+    Generic(String),
+    /// An ETF isn't supported (exit code 3)
+    NotFound(String),
+    /// Fetching or parsing data from a fund manager failed (exit code 4)
+    Upstream(String),
+}
+
+impl CliError {
+    /// Exit code of the process. 2 is left for usage errors, which clap reports.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Generic(_) => 1,
+            CliError::NotFound(_) => 3,
+            CliError::Upstream(_) => 4,
+        }
+    }
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Generic(msg) | CliError::NotFound(msg) | CliError::Upstream(msg) => {
+                write!(f, "{}", msg)
+            }
+        }
+    }
+}
+
+pub type CliResult<T> = Result<T, CliError>;
+
+/// Map an error from `ETFHoldings::etf_details()` to a CliError for an ETF.
+pub fn etf_details_error(ticker: &str, err: ETFErr) -> CliError {
+    match err {
+        ETFErr::NotFound => CliError::NotFound(format!("ETF {} isn't supported.", ticker)),
+        ETFErr::Generic(msg) => {
+            CliError::Upstream(format!("Failed to fetch details of {}: {}", ticker, msg))
+        }
+    }
+}
+
+/// Map any error to a generic CliError.
+pub fn to_cli_error(err: impl Display) -> CliError {
+    CliError::Generic(err.to_string())
+}
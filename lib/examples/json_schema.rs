@@ -0,0 +1,21 @@
+//! Writes the JSON Schemas of the serialized types to `lib/schema`.
+//!
+//! ```text
+//! $ cargo run -p etf_holdings_lib --example json_schema
+//! ```
+
+use etf_holdings_lib::{etf_json_schema, etf_list_item_json_schema};
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("schema");
+    let schemas = [
+        ("etf.schema.json", etf_json_schema()),
+        ("etf_list_item.schema.json", etf_list_item_json_schema()),
+    ];
+    for (file_name, schema) in schemas.iter() {
+        let json = serde_json::to_string_pretty(schema).expect("Failed to serialize schema");
+        fs::write(dir.join(file_name), json + "\n").expect("Failed to write schema");
+    }
+}
@@ -0,0 +1,77 @@
+//! Synchronous version of `ETFHoldings` for code that doesn't run in an async runtime.
+//!
+//! ```ignore
+//! let etf_holdings = etf_holdings_lib::blocking::ETFHoldings::new()?;
+//! let etf = etf_holdings.etf_details(&"IVV".to_string())?;
+//! ```
+
+use tokio::runtime::{Builder, Runtime};
+
+use crate::types::{BulkFetch, BulkFetchOptions, ETFListItem, ETFPosition, Error, ETF};
+use crate::ETFHoldingsOptions;
+
+/// Wraps an async `ETFHoldings` and runs its methods on a runtime of its own.
+///
+/// The methods block the current thread, so they must not be called from within an async
+/// runtime (tokio panics if they are).
+pub struct ETFHoldings {
+    inner: crate::ETFHoldings,
+    runtime: Runtime,
+}
+
+impl ETFHoldings {
+    /// Creates an instance of ETFHoldings, see `ETFHoldings::new`.
+    pub fn new() -> Result<ETFHoldings, Error> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::ETFHoldings::new())?;
+        Ok(ETFHoldings { inner, runtime })
+    }
+
+    /// Creates an instance of ETFHoldings with custom options, see `ETFHoldings::with_options`.
+    pub fn with_options(options: ETFHoldingsOptions) -> Result<ETFHoldings, Error> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::ETFHoldings::with_options(options))?;
+        Ok(ETFHoldings { inner, runtime })
+    }
+
+    /// Returns a list of supported ETFs.
+    pub fn etf_list(&self) -> Vec<ETFListItem> {
+        self.runtime.block_on(self.inner.etf_list())
+    }
+
+    /// Returns supported ETFs with the query in their ticker or name, ignoring case.
+    pub fn search(&self, query: &str) -> Vec<ETFListItem> {
+        self.runtime.block_on(self.inner.search(query))
+    }
+
+    /// Fetch ETF details and holdings for a supported ETF, see `ETFHoldings::etf_details`.
+    pub fn etf_details(&self, ticker: &String) -> Result<ETF, Error> {
+        self.runtime.block_on(self.inner.etf_details(ticker))
+    }
+
+    /// Fetch many ETFs, see `ETFHoldings::etf_details_many`.
+    pub fn etf_details_many(&self, tickers: Vec<String>, options: &BulkFetchOptions) -> BulkFetch {
+        self.runtime
+            .block_on(self.inner.etf_details_many(tickers, options))
+    }
+
+    /// Fetch every supported ETF, see `ETFHoldings::etf_details_all`.
+    pub fn etf_details_all(&self, options: &BulkFetchOptions) -> BulkFetch {
+        self.runtime.block_on(self.inner.etf_details_all(options))
+    }
+
+    /// Returns the (fetched) ETFs holding a security, see `ETFHoldings::etfs_holding`.
+    pub fn etfs_holding(&self, identifier: &str) -> Vec<ETFPosition> {
+        self.runtime.block_on(self.inner.etfs_holding(identifier))
+    }
+
+    /// Try again to set up fund managers that failed, see `ETFHoldings::retry_failed_managers`.
+    pub fn retry_failed_managers(&self) {
+        self.runtime.block_on(self.inner.retry_failed_managers())
+    }
+}
+
+/// Single threaded runtime with IO and timers for the network requests
+fn runtime() -> Result<Runtime, Error> {
+    Ok(Builder::new_current_thread().enable_all().build()?)
+}
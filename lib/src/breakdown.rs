@@ -0,0 +1,171 @@
+//! Group an ETF's holdings to see where the money is
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::types::{BreakdownBucket, BreakdownBy, Holding, ETF};
+
+impl FromStr for BreakdownBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "country" | "location" => Ok(BreakdownBy::Country),
+            "currency" => Ok(BreakdownBy::Currency),
+            "market_currency" => Ok(BreakdownBy::MarketCurrency),
+            "asset_class" => Ok(BreakdownBy::AssetClass),
+            "sector" => Ok(BreakdownBy::Sector),
+            _ => Err(format!(
+                "Unknown breakdown \"{}\", expected one of country, currency, market_currency, \
+                 asset_class or sector.",
+                s
+            )),
+        }
+    }
+}
+
+/// The bucket a holding goes in, holdings without a sector are grouped as "Unknown"
+fn bucket_key(holding: &Holding, by: BreakdownBy) -> &str {
+    match by {
+        BreakdownBy::Country => &holding.location,
+        BreakdownBy::Currency => &holding.currency,
+        BreakdownBy::MarketCurrency => &holding.market_currency,
+        BreakdownBy::AssetClass => &holding.asset_class,
+        BreakdownBy::Sector => holding.sector.as_deref().unwrap_or("Unknown"),
+    }
+}
+
+/// Group an ETF's holdings and add up their weight and market value, largest weight first.
+pub fn breakdown(etf: &ETF, by: BreakdownBy) -> Vec<BreakdownBucket> {
+    let mut buckets: HashMap<&str, BreakdownBucket> = HashMap::new();
+    for holding in &etf.holdings {
+        let key = bucket_key(holding, by);
+        let bucket = buckets.entry(key).or_insert_with(|| BreakdownBucket {
+            key: key.to_string(),
+            weight: 0.0,
+            market_value: 0.0,
+            holdings_count: 0,
+        });
+        bucket.weight += holding.weight;
+        bucket.market_value += holding.market_value;
+        bucket.holdings_count += 1;
+    }
+
+    let mut buckets: Vec<BreakdownBucket> = buckets.into_values().collect();
+    buckets.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    /// Two Australian equities traded in AUD, a US equity and USD cash
+    fn etf() -> ETF {
+        let mut bhp = mock::holding("BHP", "Equity", 35.0);
+        let mut csl = mock::holding("CSL", "Equity", 20.0);
+        for holding in [&mut bhp, &mut csl] {
+            holding.location = "Australia".to_string();
+            holding.currency = "AUD".to_string();
+            holding.market_currency = "AUD".to_string();
+        }
+        bhp.sector = Some("Materials".to_string());
+        csl.sector = Some("Health Care".to_string());
+        csl.market_currency = "USD".to_string();
+        let mut aapl = mock::holding("AAPL", "Equity", 40.0);
+        aapl.sector = Some("Information Technology".to_string());
+        let cash = mock::holding("USD", "Cash", 5.0);
+        mock::etf("AAA", vec![bhp, csl, aapl, cash])
+    }
+
+    /// Keys, weights and holdings counts of the buckets, largest first
+    fn buckets(by: BreakdownBy) -> Vec<(String, f64, usize)> {
+        breakdown(&etf(), by)
+            .into_iter()
+            .map(|bucket| (bucket.key, bucket.weight, bucket.holdings_count))
+            .collect()
+    }
+
+    fn bucket(key: &str, weight: f64, holdings_count: usize) -> (String, f64, usize) {
+        (key.to_string(), weight, holdings_count)
+    }
+
+    #[test]
+    fn parses_every_breakdown() {
+        for (input, by) in [
+            ("country", BreakdownBy::Country),
+            ("location", BreakdownBy::Country),
+            ("currency", BreakdownBy::Currency),
+            ("market_currency", BreakdownBy::MarketCurrency),
+            ("asset_class", BreakdownBy::AssetClass),
+            ("sector", BreakdownBy::Sector),
+        ] {
+            assert_eq!(input.parse::<BreakdownBy>(), Ok(by));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_breakdowns() {
+        let error = "region".parse::<BreakdownBy>().unwrap_err();
+        assert!(error.contains("\"region\""), "{}", error);
+        assert!("Country".parse::<BreakdownBy>().is_err());
+    }
+
+    #[test]
+    fn groups_by_country() {
+        assert_eq!(
+            buckets(BreakdownBy::Country),
+            [
+                bucket("Australia", 55.0, 2),
+                bucket("United States", 45.0, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_by_currency() {
+        assert_eq!(
+            buckets(BreakdownBy::Currency),
+            [bucket("AUD", 55.0, 2), bucket("USD", 45.0, 2)]
+        );
+    }
+
+    #[test]
+    fn groups_by_market_currency() {
+        assert_eq!(
+            buckets(BreakdownBy::MarketCurrency),
+            [bucket("USD", 65.0, 3), bucket("AUD", 35.0, 1)]
+        );
+    }
+
+    #[test]
+    fn groups_by_asset_class() {
+        assert_eq!(
+            buckets(BreakdownBy::AssetClass),
+            [bucket("Equity", 95.0, 3), bucket("Cash", 5.0, 1)]
+        );
+    }
+
+    #[test]
+    fn groups_holdings_without_a_sector_as_unknown() {
+        assert_eq!(
+            buckets(BreakdownBy::Sector),
+            [
+                bucket("Information Technology", 40.0, 1),
+                bucket("Materials", 35.0, 1),
+                bucket("Health Care", 20.0, 1),
+                bucket("Unknown", 5.0, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn adds_up_market_values() {
+        let buckets = breakdown(&etf(), BreakdownBy::Country);
+        let australia = buckets.iter().find(|b| b.key == "Australia").unwrap();
+        assert_eq!(australia.market_value, 55_000.0);
+        let total: f64 = buckets.iter().map(|bucket| bucket.weight).sum();
+        assert_eq!(total, 100.0);
+    }
+}
@@ -0,0 +1,174 @@
+//! Fetch many ETFs at once with bounded concurrency
+
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::HashMap;
+
+use crate::types::{BulkFetch, BulkFetchOptions, Error, FetchFailure, ETF};
+use crate::ETFHoldings;
+
+impl ETFHoldings {
+    /// Fetch many ETFs, yielding each ticker with its result as soon as it's done, so not
+    /// necessarily in the order of `tickers`.
+    ///
+    /// At most `options.concurrency` ETFs are fetched at the same time. Requests to each fund
+    /// manager are rate limited by the shared client, see `ETFHoldingsOptions::requests_per_second`.
+    ///
+    /// ```ignore
+    /// let mut results = etf_holdings.etf_details_stream(tickers, &BulkFetchOptions::default());
+    /// while let Some((ticker, result)) = results.next().await {
+    ///     ..
+    /// }
+    /// ```
+    pub fn etf_details_stream<'a>(
+        &'a self,
+        tickers: Vec<String>,
+        options: &BulkFetchOptions,
+    ) -> impl Stream<Item = (String, Result<ETF, Error>)> + 'a {
+        stream::iter(tickers)
+            .map(move |ticker| async move {
+                let result = self.etf_details(&ticker).await;
+                (ticker, result)
+            })
+            .buffer_unordered(options.concurrency.max(1))
+    }
+
+    /// Fetch many ETFs, see `etf_details_stream`. Failures don't stop the other fetches. ETFs and
+    /// failures are in the order of `tickers`.
+    pub async fn etf_details_many(
+        &self,
+        tickers: Vec<String>,
+        options: &BulkFetchOptions,
+    ) -> BulkFetch {
+        let positions: HashMap<String, usize> = tickers
+            .iter()
+            .enumerate()
+            .map(|(i, ticker)| (ticker.clone(), i))
+            .collect();
+        let mut results: Vec<(String, Result<ETF, Error>)> =
+            self.etf_details_stream(tickers, options).collect().await;
+        results.sort_by_key(|(ticker, _)| positions[ticker]);
+
+        let mut etfs = Vec::new();
+        let mut failures = Vec::new();
+        for (ticker, result) in results {
+            match result {
+                Ok(etf) => etfs.push(etf),
+                Err(error) => failures.push(FetchFailure { ticker, error }),
+            }
+        }
+        BulkFetch { etfs, failures }
+    }
+
+    /// Fetch every supported ETF, see `etf_details_many`.
+    pub async fn etf_details_all(&self, options: &BulkFetchOptions) -> BulkFetch {
+        let tickers = self
+            .etf_list()
+            .await
+            .into_iter()
+            .map(|etf| etf.ticker)
+            .collect();
+        self.etf_details_many(tickers, options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::mock::{self, MockManager};
+
+    fn tickers(tickers: &[&str]) -> Vec<String> {
+        tickers.iter().map(|ticker| ticker.to_string()).collect()
+    }
+
+    fn fetched(bulk: &BulkFetch) -> Vec<&str> {
+        bulk.etfs.iter().map(|etf| etf.ticker.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn fetches_at_most_concurrency_etfs_at_once() {
+        let names: Vec<String> = (0..10).map(|i| format!("E{}", i)).collect();
+        let etfs = names
+            .iter()
+            .map(|ticker| mock::equity_etf(ticker, &[("X", 100.0)]))
+            .collect();
+        let manager = Arc::new(MockManager::new(etfs).with_delay(Duration::from_millis(20)));
+        let etf_holdings = mock::etf_holdings(vec![manager.clone()]).await;
+
+        let bulk = etf_holdings
+            .etf_details_many(names, &BulkFetchOptions { concurrency: 3 })
+            .await;
+        assert_eq!(bulk.etfs.len(), 10);
+        assert_eq!(manager.max_concurrent_calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn zero_concurrency_still_fetches() {
+        let manager = Arc::new(MockManager::new(vec![mock::equity_etf("AAA", &[])]));
+        let etf_holdings = mock::etf_holdings(vec![manager.clone()]).await;
+        let bulk = etf_holdings
+            .etf_details_many(tickers(&["AAA"]), &BulkFetchOptions { concurrency: 0 })
+            .await;
+        assert_eq!(fetched(&bulk), ["AAA"]);
+        assert_eq!(manager.max_concurrent_calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn failures_are_reported_without_stopping_the_batch() {
+        let manager = MockManager::new(vec![
+            mock::equity_etf("AAA", &[("X", 100.0)]),
+            mock::equity_etf("CCC", &[("Y", 100.0)]),
+        ])
+        .failing("BBB");
+        let etf_holdings = mock::etf_holdings(vec![Arc::new(manager)]).await;
+
+        let bulk = etf_holdings
+            .etf_details_many(
+                tickers(&["AAA", "BBB", "ZZZ", "CCC"]),
+                &BulkFetchOptions::default(),
+            )
+            .await;
+        assert_eq!(fetched(&bulk), ["AAA", "CCC"]);
+        assert_eq!(bulk.failures.len(), 2);
+        assert_eq!(bulk.failures[0].ticker, "BBB");
+        assert!(matches!(&bulk.failures[0].error, Error::Generic(msg) if msg.contains("BBB")));
+        assert_eq!(bulk.failures[1].ticker, "ZZZ");
+        assert!(matches!(bulk.failures[1].error, Error::NotFound));
+
+        let all = etf_holdings
+            .etf_details_all(&BulkFetchOptions::default())
+            .await;
+        assert_eq!(fetched(&all), ["AAA", "CCC"]);
+        assert_eq!(all.failures.len(), 1);
+        assert_eq!(all.failures[0].ticker, "BBB");
+    }
+
+    #[tokio::test]
+    async fn stream_yields_in_completion_order_and_many_in_ticker_order() {
+        let manager = MockManager::new(vec![
+            mock::equity_etf("AAA", &[("X", 100.0)]),
+            mock::equity_etf("BBB", &[("Y", 100.0)]),
+            mock::equity_etf("CCC", &[("Z", 100.0)]),
+        ])
+        .with_ticker_delay("AAA", Duration::from_millis(60))
+        .with_ticker_delay("BBB", Duration::from_millis(30));
+        let etf_holdings = mock::etf_holdings(vec![Arc::new(manager)]).await;
+        let options = BulkFetchOptions { concurrency: 3 };
+
+        let streamed: Vec<String> = etf_holdings
+            .etf_details_stream(tickers(&["AAA", "BBB", "CCC"]), &options)
+            .map(|(ticker, _)| ticker)
+            .collect()
+            .await;
+        assert_eq!(streamed, ["CCC", "BBB", "AAA"]);
+
+        // Forget the fetched ETFs so they take as long again
+        etf_holdings.reload_ticker_mapping().await.unwrap();
+        let bulk = etf_holdings
+            .etf_details_many(tickers(&["AAA", "BBB", "CCC"]), &options)
+            .await;
+        assert_eq!(fetched(&bulk), ["AAA", "BBB", "CCC"]);
+    }
+}
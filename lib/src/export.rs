@@ -0,0 +1,334 @@
+//! Write ETF snapshots as tables with one row per holding, for spreadsheets and data tools
+
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::types::{Error, ExportFormat, ExportRow, ETF};
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "jsonl" | "json_lines" => Ok(ExportFormat::JsonLines),
+            "parquet" => Ok(ExportFormat::Parquet),
+            "xlsx" => Ok(ExportFormat::Xlsx),
+            _ => Err(format!(
+                "Unknown export format \"{}\", expected one of csv, jsonl, parquet or xlsx.",
+                s
+            )),
+        }
+    }
+}
+
+/// Flatten ETF snapshots into one row per holding, in the order of the ETFs and their holdings.
+pub fn export_rows(etfs: &[ETF]) -> Vec<ExportRow> {
+    let mut rows = Vec::new();
+    for etf in etfs {
+        for holding in &etf.holdings {
+            rows.push(ExportRow {
+                etf_ticker: etf.ticker.clone(),
+                etf_name: etf.name.clone(),
+                as_of: etf.last_update.clone(),
+                ticker: holding.ticker.clone(),
+                name: holding.name.clone(),
+                asset_class: holding.asset_class.clone(),
+                sector: holding.sector.clone(),
+                weight: holding.weight,
+                market_value: holding.market_value,
+                notional_value: holding.notional_value,
+                shares: holding.shares,
+                price: holding.price,
+                location: holding.location.clone(),
+                exchange: holding.exchange.clone(),
+                currency: holding.currency.clone(),
+                fx_rate: holding.fx_rate,
+                market_currency: holding.market_currency.clone(),
+                yahoo: holding.symbols.yahoo.clone(),
+                bloomberg: holding.symbols.bloomberg.clone(),
+                google: holding.symbols.google.clone(),
+                refinitiv: holding.symbols.refinitiv.clone(),
+            });
+        }
+    }
+    rows
+}
+
+/// Write one or many ETF snapshots in a tabular format.
+///
+/// Every format has the same columns, the fields of `ExportRow` in order. Missing values are
+/// empty cells in CSV and XLSX, `null` in JSON Lines and Parquet.
+///
+/// ```ignore
+/// let etf = etf_holdings.etf_details(&"IVV".to_string()).await?;
+/// export(&[etf], ExportFormat::Csv, std::io::stdout())?;
+/// ```
+pub fn export<W: Write + Send>(etfs: &[ETF], format: ExportFormat, writer: W) -> Result<(), Error> {
+    let rows = export_rows(etfs);
+    match format {
+        ExportFormat::Csv => write_csv(&rows, writer),
+        ExportFormat::JsonLines => write_json_lines(&rows, writer),
+        ExportFormat::Parquet => write_parquet(&rows, writer),
+        ExportFormat::Xlsx => write_xlsx(&rows, writer),
+    }
+}
+
+fn write_csv<W: Write>(rows: &[ExportRow], writer: W) -> Result<(), Error> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    if rows.is_empty() {
+        // serialize() only writes the header with the first row
+        csv_writer.write_record(COLUMNS.iter().map(|(name, _)| name))?;
+    }
+    for row in rows {
+        csv_writer.serialize(row)?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+fn write_json_lines<W: Write>(rows: &[ExportRow], mut writer: W) -> Result<(), Error> {
+    for row in rows {
+        serde_json::to_writer(&mut writer, row)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Type of a column, to write typed cells in Parquet and XLSX
+#[derive(Clone, Copy, PartialEq)]
+enum ColumnType {
+    Text,
+    Number,
+}
+
+/// Columns of an export, must match the fields of `ExportRow` (the tests check every format)
+const COLUMNS: [(&str, ColumnType); 21] = [
+    ("etf_ticker", ColumnType::Text),
+    ("etf_name", ColumnType::Text),
+    ("as_of", ColumnType::Text),
+    ("ticker", ColumnType::Text),
+    ("name", ColumnType::Text),
+    ("asset_class", ColumnType::Text),
+    ("sector", ColumnType::Text),
+    ("weight", ColumnType::Number),
+    ("market_value", ColumnType::Number),
+    ("notional_value", ColumnType::Number),
+    ("shares", ColumnType::Number),
+    ("price", ColumnType::Number),
+    ("location", ColumnType::Text),
+    ("exchange", ColumnType::Text),
+    ("currency", ColumnType::Text),
+    ("fx_rate", ColumnType::Number),
+    ("market_currency", ColumnType::Text),
+    ("yahoo", ColumnType::Text),
+    ("bloomberg", ColumnType::Text),
+    ("google", ColumnType::Text),
+    ("refinitiv", ColumnType::Text),
+];
+
+/// A value in an export
+#[cfg_attr(not(any(feature = "parquet", feature = "xlsx")), allow(dead_code))]
+#[derive(Clone, Copy)]
+enum Cell<'a> {
+    Text(Option<&'a str>),
+    Number(Option<f64>),
+}
+
+/// Values of a row in the order of `COLUMNS`
+#[cfg_attr(not(any(feature = "parquet", feature = "xlsx")), allow(dead_code))]
+fn cells(row: &ExportRow) -> [Cell<'_>; 21] {
+    [
+        Cell::Text(Some(&row.etf_ticker)),
+        Cell::Text(Some(&row.etf_name)),
+        Cell::Text(Some(&row.as_of)),
+        Cell::Text(Some(&row.ticker)),
+        Cell::Text(Some(&row.name)),
+        Cell::Text(Some(&row.asset_class)),
+        Cell::Text(row.sector.as_deref()),
+        Cell::Number(Some(row.weight)),
+        Cell::Number(Some(row.market_value)),
+        Cell::Number(row.notional_value),
+        Cell::Number(row.shares),
+        Cell::Number(row.price),
+        Cell::Text(Some(&row.location)),
+        Cell::Text(Some(&row.exchange)),
+        Cell::Text(Some(&row.currency)),
+        Cell::Number(row.fx_rate),
+        Cell::Text(Some(&row.market_currency)),
+        Cell::Text(row.yahoo.as_deref()),
+        Cell::Text(row.bloomberg.as_deref()),
+        Cell::Text(row.google.as_deref()),
+        Cell::Text(row.refinitiv.as_deref()),
+    ]
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet<W: Write + Send>(rows: &[ExportRow], writer: W) -> Result<(), Error> {
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray};
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    let rows: Vec<[Cell; 21]> = rows.iter().map(cells).collect();
+    let columns = COLUMNS.iter().enumerate().map(|(i, (name, column_type))| {
+        let array: ArrayRef = match column_type {
+            ColumnType::Text => Arc::new(
+                rows.iter()
+                    .map(|row| match row[i] {
+                        Cell::Text(value) => value,
+                        Cell::Number(_) => None,
+                    })
+                    .collect::<StringArray>(),
+            ),
+            ColumnType::Number => Arc::new(
+                rows.iter()
+                    .map(|row| match row[i] {
+                        Cell::Number(value) => value,
+                        Cell::Text(_) => None,
+                    })
+                    .collect::<Float64Array>(),
+            ),
+        };
+        (name, array)
+    });
+    let batch = RecordBatch::try_from_iter(columns)?;
+
+    let mut parquet_writer = ArrowWriter::try_new(writer, batch.schema(), None)?;
+    parquet_writer.write(&batch)?;
+    parquet_writer.close()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet<W: Write>(_rows: &[ExportRow], _writer: W) -> Result<(), Error> {
+    Err(Error::from(
+        "Parquet export needs the parquet feature of etf_holdings_lib.",
+    ))
+}
+
+#[cfg(feature = "xlsx")]
+fn write_xlsx<W: Write>(rows: &[ExportRow], mut writer: W) -> Result<(), Error> {
+    use rust_xlsxwriter::Workbook;
+
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    for (col, (name, _)) in COLUMNS.iter().enumerate() {
+        worksheet.write_string(0, col as u16, *name)?;
+    }
+    for (i, row) in rows.iter().enumerate() {
+        let xlsx_row = i as u32 + 1;
+        for (col, cell) in cells(row).iter().enumerate() {
+            match cell {
+                Cell::Text(Some(value)) => {
+                    worksheet.write_string(xlsx_row, col as u16, *value)?;
+                }
+                Cell::Number(Some(value)) => {
+                    worksheet.write_number(xlsx_row, col as u16, *value)?;
+                }
+                Cell::Text(None) | Cell::Number(None) => {}
+            }
+        }
+    }
+    writer.write_all(&workbook.save_to_buffer()?)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "xlsx"))]
+fn write_xlsx<W: Write>(_rows: &[ExportRow], _writer: W) -> Result<(), Error> {
+    Err(Error::from(
+        "XLSX export needs the xlsx feature of etf_holdings_lib.",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    fn column_names() -> Vec<&'static str> {
+        COLUMNS.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Rows with every optional value present and one without any
+    fn etfs() -> Vec<ETF> {
+        let mut cash = mock::holding("USD", "Cash", 1.0);
+        cash.sector = None;
+        cash.notional_value = None;
+        cash.shares = None;
+        cash.price = None;
+        cash.fx_rate = None;
+        let mut aapl = mock::holding("AAPL", "Equity", 99.0);
+        aapl.sector = Some("Information Technology".to_string());
+        aapl.symbols.refinitiv = Some("AAPL.OQ".to_string());
+        vec![mock::etf("AAA", vec![aapl, cash])]
+    }
+
+    #[test]
+    fn csv_header_is_the_columns() {
+        for etfs in [etfs(), Vec::new()] {
+            let mut csv = Vec::new();
+            export(&etfs, ExportFormat::Csv, &mut csv).unwrap();
+            let csv = String::from_utf8(csv).unwrap();
+            let header: Vec<&str> = csv.lines().next().unwrap().split(',').collect();
+            assert_eq!(header, column_names());
+        }
+    }
+
+    #[test]
+    fn cells_are_the_fields_of_the_columns() {
+        for row in export_rows(&etfs()) {
+            let fields = match serde_json::to_value(&row).unwrap() {
+                serde_json::Value::Object(fields) => fields,
+                other => panic!("{:?}", other),
+            };
+            assert_eq!(fields.len(), COLUMNS.len());
+
+            for ((name, column_type), cell) in COLUMNS.iter().zip(cells(&row).iter()) {
+                let field = &fields[*name];
+                match (column_type, cell) {
+                    (ColumnType::Text, Cell::Text(value)) => {
+                        assert_eq!(field.as_str(), *value, "{}", name)
+                    }
+                    (ColumnType::Number, Cell::Number(value)) => {
+                        assert_eq!(field.as_f64(), *value, "{}", name)
+                    }
+                    _ => panic!("{} has a cell of the wrong type", name),
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_schema_is_the_columns() {
+        use parquet::basic::Type;
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let path = std::env::temp_dir().join(format!("etf-export-{}.parquet", std::process::id()));
+        export(
+            &etfs(),
+            ExportFormat::Parquet,
+            std::fs::File::create(&path).unwrap(),
+        )
+        .unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(metadata.file_metadata().num_rows(), 2);
+        let schema = metadata.file_metadata().schema_descr();
+        let names: Vec<&str> = schema
+            .columns()
+            .iter()
+            .map(|column| column.name())
+            .collect();
+        assert_eq!(names, column_names());
+        for (column, (name, column_type)) in schema.columns().iter().zip(COLUMNS.iter()) {
+            let expected = match column_type {
+                ColumnType::Text => Type::BYTE_ARRAY,
+                ColumnType::Number => Type::DOUBLE,
+            };
+            assert_eq!(column.physical_type(), expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Economic exposure of an ETF's holdings, counting derivatives at their notional value
+//!
+//! Futures, swaps and FX forwards have a market value close to zero (only the unrealised profit or
+//! loss) but can expose the fund to much more than that. Weighting by market value makes leveraged
+//! and currency hedged funds look like they hold mostly cash.
+
+use crate::nav::net_assets;
+use crate::types::{Exposure, ExposureSummary, Holding, HoldingExposure, ETF};
+
+/// Asset classes whose exposure is their notional value
+const DERIVATIVE_ASSET_CLASSES: [&str; 6] = [
+    "Futures",
+    "Swaps",
+    "FX",
+    "Forwards",
+    "Options",
+    "Derivatives",
+];
+
+/// Returns true if the holding is a derivative.
+pub fn is_derivative(holding: &Holding) -> bool {
+    DERIVATIVE_ASSET_CLASSES
+        .iter()
+        .any(|class| holding.asset_class.eq_ignore_ascii_case(class))
+}
+
+/// Exposure of a holding, notional value for derivatives and market value for everything else.
+/// Derivatives without a notional value in the holdings file fall back to their market value.
+/// Negative exposure is a short position.
+pub fn holding_exposure(holding: &Holding) -> f64 {
+    if is_derivative(holding) {
+        holding.notional_value.unwrap_or(holding.market_value)
+    } else {
+        holding.market_value
+    }
+}
+
+/// Calculate the exposure of every holding and the fund's gross and net leverage.
+///
+/// Weights and leverage are relative to the fund's net assets (see `net_assets`). Derivatives count
+/// at their notional value, or their market value if they don't have one (see `holding_exposure`).
+pub fn exposure(etf: &ETF) -> Exposure {
+    let net_assets = net_assets(etf);
+    let relative = |value: f64| {
+        if net_assets != 0.0 {
+            value / net_assets
+        } else {
+            0.0
+        }
+    };
+
+    let mut long_exposure = 0.0;
+    let mut short_exposure = 0.0;
+    let mut holdings = Vec::new();
+    for holding in &etf.holdings {
+        let exposure = holding_exposure(holding);
+        if exposure >= 0.0 {
+            long_exposure += exposure;
+        } else {
+            short_exposure -= exposure;
+        }
+        holdings.push(HoldingExposure {
+            ticker: holding.ticker.clone(),
+            name: holding.name.clone(),
+            asset_class: holding.asset_class.clone(),
+            derivative: is_derivative(holding),
+            exposure,
+            weight: relative(exposure) * 100.0,
+        });
+    }
+    holdings.sort_by(|a, b| b.exposure.abs().total_cmp(&a.exposure.abs()));
+
+    let gross_exposure = long_exposure + short_exposure;
+    let net_exposure = long_exposure - short_exposure;
+    Exposure {
+        summary: ExposureSummary {
+            net_assets,
+            long_exposure,
+            short_exposure,
+            gross_exposure,
+            net_exposure,
+            gross_leverage: relative(gross_exposure),
+            net_leverage: relative(net_exposure),
+        },
+        holdings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    fn derivative(ticker: &str, asset_class: &str, notional_value: Option<f64>) -> Holding {
+        Holding {
+            market_value: 0.0,
+            weight: 0.0,
+            notional_value,
+            shares: None,
+            price: None,
+            ..mock::holding(ticker, asset_class, 0.0)
+        }
+    }
+
+    /// 90,000 USD of equities and 10,000 USD cash, hedged with a short future and an FX forward
+    fn etf() -> ETF {
+        mock::etf(
+            "AAA",
+            vec![
+                mock::holding("X", "Equity", 90.0),
+                mock::holding("USD", "Cash", 10.0),
+                derivative("ESZ1", "Futures", Some(-50_000.0)),
+                derivative("AUD", "FX", Some(-40_000.0)),
+            ],
+        )
+    }
+
+    #[test]
+    fn derivatives_count_at_their_notional_value() {
+        let exposure = exposure(&etf());
+        let by_ticker = |ticker: &str| {
+            exposure
+                .holdings
+                .iter()
+                .find(|holding| holding.ticker == ticker)
+                .unwrap()
+        };
+        assert_eq!(by_ticker("ESZ1").exposure, -50_000.0);
+        assert_eq!(by_ticker("ESZ1").weight, -50.0);
+        assert!(by_ticker("ESZ1").derivative);
+        assert_eq!(by_ticker("AUD").exposure, -40_000.0);
+        assert_eq!(by_ticker("USD").exposure, 10_000.0);
+        assert!(!by_ticker("USD").derivative);
+        assert_eq!(by_ticker("X").weight, 90.0);
+
+        let tickers: Vec<&str> = exposure
+            .holdings
+            .iter()
+            .map(|h| h.ticker.as_str())
+            .collect();
+        assert_eq!(tickers, ["X", "ESZ1", "AUD", "USD"]);
+    }
+
+    #[test]
+    fn long_short_gross_and_net() {
+        let summary = exposure(&etf()).summary;
+        assert_eq!(summary.net_assets, 100_000.0);
+        assert_eq!(summary.long_exposure, 100_000.0);
+        assert_eq!(summary.short_exposure, 90_000.0);
+        assert_eq!(summary.gross_exposure, 190_000.0);
+        assert_eq!(summary.net_exposure, 10_000.0);
+        assert_eq!(summary.gross_leverage, 1.9);
+        assert_eq!(summary.net_leverage, 0.1);
+    }
+
+    #[test]
+    fn derivatives_without_a_notional_value_count_at_market_value() {
+        let mut swap = derivative("SWAP", "Swaps", None);
+        swap.market_value = 2_000.0;
+        assert_eq!(holding_exposure(&swap), 2_000.0);
+
+        // Equities' notional value is ignored
+        let mut equity = mock::holding("X", "Equity", 10.0);
+        equity.notional_value = Some(1.0);
+        assert_eq!(holding_exposure(&equity), 10_000.0);
+    }
+
+    #[test]
+    fn no_leverage_without_net_assets() {
+        let etf = mock::etf("AAA", vec![derivative("ESZ1", "Futures", Some(50_000.0))]);
+        let exposure = exposure(&etf);
+        assert_eq!(exposure.summary.gross_exposure, 50_000.0);
+        assert_eq!(exposure.summary.gross_leverage, 0.0);
+        assert_eq!(exposure.holdings[0].weight, 0.0);
+    }
+}
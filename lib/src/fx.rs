@@ -0,0 +1,181 @@
+//! Convert holdings into another currency
+//!
+//! Holdings come with the ETF's currency (`ETF::currency`) for market and notional values, the
+//! trading currency for prices (`Holding::currency`) and an FX rate from the trading currency to the
+//! ETF's currency (`Holding::fx_rate`).
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+use crate::types::{Error, ETF};
+
+/// Somewhere to get exchange rates from
+#[async_trait]
+pub trait FxRateSource: Send + Sync {
+    /// Returns how many units of currency `to` one unit of currency `from` is worth.
+    async fn rate(&self, from: &str, to: &str) -> Result<f64, Error>;
+}
+
+/// Exchange rates implied by a fund's own holdings.
+///
+/// Every holding's FX rate converts its trading currency to the fund's currency, so the fund can
+/// convert between any currencies it holds something in. These rates are from the same date as the
+/// holdings, unlike the latest rates from an external source.
+#[derive(Debug, Clone)]
+pub struct FundFxRates {
+    /// Currency -> value of one unit in the fund's currency
+    to_fund_currency: HashMap<String, f64>,
+}
+
+impl FundFxRates {
+    pub fn new(etf: &ETF) -> Self {
+        let mut to_fund_currency = HashMap::new();
+        to_fund_currency.insert(etf.currency.clone(), 1.0);
+        for holding in &etf.holdings {
+            if let Some(fx_rate) = holding.fx_rate {
+                if fx_rate > 0.0 {
+                    to_fund_currency
+                        .entry(holding.currency.clone())
+                        .or_insert(fx_rate);
+                }
+            }
+        }
+        FundFxRates { to_fund_currency }
+    }
+}
+
+#[async_trait]
+impl FxRateSource for FundFxRates {
+    async fn rate(&self, from: &str, to: &str) -> Result<f64, Error> {
+        let missing = |currency: &str| {
+            Error::from(format!(
+                "The fund has no holdings in {} to get an FX rate from.",
+                currency
+            ))
+        };
+        let from_rate = self
+            .to_fund_currency
+            .get(from)
+            .ok_or_else(|| missing(from))?;
+        let to_rate = self.to_fund_currency.get(to).ok_or_else(|| missing(to))?;
+        Ok(from_rate / to_rate)
+    }
+}
+
+/// Rates from `primary`, or from `fallback` for currencies `primary` can't convert, e.g. fund rates
+/// falling back to market rates for currencies the fund doesn't hold.
+pub struct FallbackFxRates<'a> {
+    pub primary: &'a dyn FxRateSource,
+    pub fallback: &'a dyn FxRateSource,
+}
+
+#[async_trait]
+impl FxRateSource for FallbackFxRates<'_> {
+    async fn rate(&self, from: &str, to: &str) -> Result<f64, Error> {
+        match self.primary.rate(from, to).await {
+            Ok(rate) => Ok(rate),
+            Err(_) => self.fallback.rate(from, to).await,
+        }
+    }
+}
+
+/// Returns a copy of the ETF with market values, prices and notional values in `base_currency`.
+///
+/// `ETF::currency` becomes the base currency and every converted holding's FX rate becomes 1 since
+/// its price is now in the same currency as its market value. `Holding::currency` and
+/// `Holding::market_currency` still say where the security trades.
+pub async fn convert_etf(
+    etf: &ETF,
+    base_currency: &str,
+    rates: &dyn FxRateSource,
+) -> Result<ETF, Error> {
+    let fund_rate = rates.rate(&etf.currency, base_currency).await?;
+
+    // Look up each trading currency once
+    let mut price_rates: HashMap<String, f64> = HashMap::new();
+    let mut converted = etf.clone();
+    for holding in converted.holdings.iter_mut() {
+        holding.market_value *= fund_rate;
+        holding.notional_value = holding.notional_value.map(|value| value * fund_rate);
+
+        if let Some(price) = holding.price {
+            let price_rate = match price_rates.get(&holding.currency) {
+                Some(rate) => *rate,
+                None => {
+                    let rate = rates.rate(&holding.currency, base_currency).await?;
+                    price_rates.insert(holding.currency.clone(), rate);
+                    rate
+                }
+            };
+            holding.price = Some(price * price_rate);
+            holding.fx_rate = Some(1.0);
+        }
+    }
+    converted.currency = base_currency.to_string();
+    Ok(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    /// Fixed rates to USD
+    struct UsdRates;
+
+    #[async_trait]
+    impl FxRateSource for UsdRates {
+        async fn rate(&self, from: &str, to: &str) -> Result<f64, Error> {
+            let to_usd = |currency: &str| match currency {
+                "USD" => Ok(1.0),
+                "AUD" => Ok(0.75),
+                "JPY" => Ok(0.01),
+                _ => Err(Error::NotFound),
+            };
+            Ok(to_usd(from)? / to_usd(to)?)
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_for_currencies_the_fund_does_not_hold() {
+        let mut aapl = mock::holding("AAPL", "Equity", 50.0);
+        aapl.market_value = 1000.0;
+        aapl.price = Some(150.0);
+        // Missing rate, e.g. a placeholder row
+        let mut sony = mock::holding("6758", "Equity", 50.0);
+        sony.currency = "JPY".to_string();
+        sony.market_value = 1000.0;
+        sony.price = Some(12000.0);
+        sony.fx_rate = None;
+        let etf = mock::etf("AAA", vec![aapl, sony]);
+
+        let fund_rates = FundFxRates::new(&etf);
+        assert!(convert_etf(&etf, "AUD", &fund_rates).await.is_err());
+
+        let rates = FallbackFxRates {
+            primary: &fund_rates,
+            fallback: &UsdRates,
+        };
+        let converted = convert_etf(&etf, "AUD", &rates).await.unwrap();
+        assert_eq!(converted.currency, "AUD");
+        assert_eq!(converted.holdings[0].market_value, 1000.0 / 0.75);
+        assert_eq!(converted.holdings[0].price, Some(150.0 / 0.75));
+        assert_eq!(converted.holdings[1].price, Some(12000.0 * 0.01 / 0.75));
+    }
+
+    #[tokio::test]
+    async fn prefers_the_fund_rates() {
+        let mut sony = mock::holding("6758", "Equity", 100.0);
+        sony.currency = "JPY".to_string();
+        sony.fx_rate = Some(0.009);
+        let etf = mock::etf("AAA", vec![sony]);
+
+        let fund_rates = FundFxRates::new(&etf);
+        let rates = FallbackFxRates {
+            primary: &fund_rates,
+            fallback: &UsdRates,
+        };
+        assert_eq!(rates.rate("JPY", "USD").await.unwrap(), 0.009);
+        assert_eq!(rates.rate("AUD", "USD").await.unwrap(), 0.75);
+    }
+}
@@ -0,0 +1,164 @@
+//! Reverse index from holdings to the ETFs holding them
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::symbology::security_identifier;
+use crate::types::{ETFPosition, ETF};
+
+/// Index of qualified security identifiers (Yahoo, Bloomberg, Google and Refinitiv symbols) to
+/// the fetched ETFs holding them.
+///
+/// Only securities with a qualified identifier are indexed (see `security_identifier`). Bare local
+/// tickers aren't unique across exchanges and cash is listed under its currency, indexing those
+/// would mix up unrelated holdings.
+///
+/// Identifiers are stored upper case so lookups are case insensitive.
+#[derive(Default)]
+pub struct HoldingIndex {
+    /// Identifier -> ETF ticker -> position
+    positions: HashMap<String, BTreeMap<String, ETFPosition>>,
+    /// ETF ticker -> identifiers it's indexed under, to remove stale entries on update
+    indexed: HashMap<String, Vec<String>>,
+}
+
+impl HoldingIndex {
+    /// Index (or re-index) the holdings of an ETF, replacing its previous snapshot.
+    pub fn update(&mut self, etf: &ETF) {
+        self.remove(&etf.ticker);
+
+        // Securities listed more than once (e.g. different share classes of a line) are combined
+        let mut etf_positions: HashMap<&str, ETFPosition> = HashMap::new();
+        let mut identifiers: HashMap<&str, Vec<String>> = HashMap::new();
+        for holding in &etf.holdings {
+            let security = match security_identifier(holding) {
+                Some(security) => security,
+                None => continue,
+            };
+            let position = etf_positions
+                .entry(security)
+                .or_insert_with(|| ETFPosition {
+                    etf_ticker: etf.ticker.clone(),
+                    etf_name: etf.name.clone(),
+                    holding_ticker: security.to_string(),
+                    holding_name: holding.name.clone(),
+                    weight: 0.0,
+                    shares: None,
+                    last_update: etf.last_update.clone(),
+                });
+            position.weight += holding.weight;
+            if let Some(shares) = holding.shares {
+                position.shares = Some(position.shares.unwrap_or(0.0) + shares);
+            }
+
+            let symbols = &holding.symbols;
+            let security_identifiers = identifiers.entry(security).or_default();
+            let candidates = [
+                symbols.yahoo.as_ref(),
+                symbols.bloomberg.as_ref(),
+                symbols.google.as_ref(),
+                symbols.refinitiv.as_ref(),
+            ];
+            for identifier in candidates.iter().flatten() {
+                let identifier = identifier.to_uppercase();
+                if !security_identifiers.contains(&identifier) {
+                    security_identifiers.push(identifier);
+                }
+            }
+        }
+
+        let mut indexed = Vec::new();
+        for (security, position) in etf_positions {
+            for identifier in identifiers.remove(security).unwrap_or_default() {
+                self.positions
+                    .entry(identifier.clone())
+                    .or_default()
+                    .insert(etf.ticker.clone(), position.clone());
+                indexed.push(identifier);
+            }
+        }
+        self.indexed.insert(etf.ticker.clone(), indexed);
+    }
+
+    /// Remove all entries of an ETF.
+    pub fn remove(&mut self, etf_ticker: &str) {
+        for identifier in self.indexed.remove(etf_ticker).unwrap_or_default() {
+            if let Some(etfs) = self.positions.get_mut(&identifier) {
+                etfs.remove(etf_ticker);
+                if etfs.is_empty() {
+                    self.positions.remove(&identifier);
+                }
+            }
+        }
+    }
+
+    /// Returns the positions of all indexed ETFs in a holding, largest weight first.
+    pub fn lookup(&self, identifier: &str) -> Vec<ETFPosition> {
+        let mut positions: Vec<ETFPosition> = self
+            .positions
+            .get(&identifier.to_uppercase())
+            .map(|etfs| etfs.values().cloned().collect())
+            .unwrap_or_default();
+        positions.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+        positions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+    use crate::symbology::vendor_symbols;
+    use crate::ticker::TickerMapping;
+
+    #[test]
+    fn indexes_qualified_identifiers_of_securities() {
+        let mut bhp = mock::holding("BHP", "Equity", 5.0);
+        bhp.ticker = "BHP.AX".to_string();
+        bhp.symbols = vendor_symbols(&TickerMapping::default(), "BHP", "Asx - All Markets");
+        let mut etf = mock::equity_etf("AAA", &[("AAPL", 60.0)]);
+        etf.holdings.push(bhp);
+        etf.holdings.push(mock::holding("USD", "Cash", 20.0));
+        etf.holdings.push(mock::holding("-", "Cash", 15.0));
+
+        let mut index = HoldingIndex::default();
+        index.update(&etf);
+
+        for identifier in ["BHP.AX", "bhp au equity", "ASX:BHP"] {
+            let positions = index.lookup(identifier);
+            assert_eq!(positions.len(), 1, "{}", identifier);
+            assert_eq!(positions[0].holding_ticker, "BHP.AX");
+            assert_eq!(positions[0].weight, 5.0);
+        }
+        assert_eq!(index.lookup("AAPL").len(), 1);
+        // Bare local tickers, cash and placeholders aren't indexed
+        assert!(index.lookup("BHP").is_empty());
+        assert!(index.lookup("USD").is_empty());
+        assert!(index.lookup("-").is_empty());
+    }
+
+    #[test]
+    fn holdings_on_unmapped_exchanges_are_not_indexed() {
+        let mut etf = mock::equity_etf("AAA", &[("ABC", 100.0)]);
+        etf.holdings[0].symbols =
+            vendor_symbols(&TickerMapping::default(), "ABC", "Mystery Exchange");
+        let mut index = HoldingIndex::default();
+        index.update(&etf);
+        assert!(index.lookup("ABC").is_empty());
+    }
+
+    #[test]
+    fn updates_replace_the_previous_snapshot() {
+        let mut index = HoldingIndex::default();
+        index.update(&mock::equity_etf("AAA", &[("AAPL", 50.0), ("MSFT", 50.0)]));
+        index.update(&mock::equity_etf("BBB", &[("AAPL", 10.0)]));
+        index.update(&mock::equity_etf("AAA", &[("MSFT", 100.0)]));
+
+        let aapl = index.lookup("AAPL");
+        assert_eq!(aapl.len(), 1);
+        assert_eq!(aapl[0].etf_ticker, "BBB");
+        assert_eq!(index.lookup("MSFT")[0].weight, 100.0);
+
+        index.remove("BBB");
+        assert!(index.lookup("AAPL").is_empty());
+    }
+}
@@ -5,9 +5,31 @@ use scraper::{Html, Selector};
 use serde::Deserialize;
 use std::collections::HashMap;
 
-use crate::deserialize_formatted_floats;
-use crate::ticker::fully_qualified_ticker;
-use crate::types::{ETFListItem, Error, FundManager, Holding, ETF};
+use crate::numbers::NumberFormat;
+use crate::symbology::vendor_symbols;
+use crate::ticker::TickerMapping;
+use crate::types::{
+    ETFListItem, Error, FundManager, Holding, ManagerContext, ParseWarning, UnresolvedHolding,
+    ValidationReport, ETF, SCHEMA_VERSION,
+};
+use crate::upstream::UpstreamClient;
+
+/// An iShares regional site, each one formats the numbers in its holdings files its own way
+#[derive(Debug, Clone, Copy)]
+struct IshareSite {
+    /// Path of the site on www.ishares.com, e.g. `/us`
+    path: &'static str,
+    /// Currency of market values in the site's holdings files
+    currency: &'static str,
+    number_format: NumberFormat,
+}
+
+/// The US site, the only one whose ETF list and holdings files we understand so far
+const US_SITE: IshareSite = IshareSite {
+    path: "/us",
+    currency: "USD",
+    number_format: NumberFormat::English,
+};
 
 #[derive(Debug)]
 struct IshareETFListItem {
@@ -18,15 +40,17 @@ struct IshareETFListItem {
 
 #[derive(Debug)]
 pub struct Ishare {
-    fetched_etfs: HashMap<String, ETF>,
     etf_list: HashMap<String, IshareETFListItem>,
+    site: IshareSite,
+    context: ManagerContext,
 }
 
 #[async_trait]
 impl FundManager for Ishare {
-    async fn new() -> Result<Self, Error> {
+    async fn new(context: ManagerContext) -> Result<Self, Error> {
+        let site = US_SITE;
         let etf_list = {
-            match fetch_etf_list().await {
+            match fetch_etf_list(&context.upstream, &site).await {
                 Ok(x) => x,
                 Err(err) => {
                     return Err(Error::from(format!(
@@ -37,8 +61,9 @@ impl FundManager for Ishare {
             }
         };
         Ok(Ishare {
-            fetched_etfs: HashMap::new(),
-            etf_list: etf_list,
+            etf_list,
+            site,
+            context,
         })
     }
 
@@ -52,31 +77,37 @@ impl FundManager for Ishare {
             .collect()
     }
 
-    async fn etf_details(&mut self, ticker: &String) -> Result<ETF, Error> {
-        if let Some(etf) = self.fetched_etfs.get(ticker) {
-            return Ok(etf.clone());
-        }
+    fn host(&self) -> &str {
+        "www.ishares.com"
+    }
 
+    async fn etf_details(&self, ticker: &str) -> Result<ETF, Error> {
         let etf_item = self
             .etf_list
             .get(ticker)
             .ok_or(format!("{} not found in iShare fund manager.", ticker))?;
-        match fetch_holdings(&etf_item).await {
-            Ok(etf) => {
-                self.fetched_etfs.insert(ticker.clone(), etf);
-                Ok(self.fetched_etfs.get(ticker).unwrap().clone())
-            }
-            Err(x) => Err(Error::from(format!(
-                "Error Ishare::etf_details({}): {:?}",
-                ticker, x
-            ))),
-        }
+        let ticker_mapping = self.context.ticker_mapping.read().await.clone();
+        fetch_holdings(
+            &self.context.upstream,
+            &self.site,
+            etf_item,
+            &ticker_mapping,
+            self.context.lenient_parsing,
+        )
+        .await
+        .map_err(|x| Error::from(format!("Error Ishare::etf_details({}): {:?}", ticker, x)))
     }
 }
 
-async fn fetch_etf_list() -> Result<HashMap<String, IshareETFListItem>, Error> {
-    let url = "https://www.ishares.com/us/products/etf-investments";
-    let html = reqwest::get(url).await?.text().await?;
+async fn fetch_etf_list(
+    upstream: &UpstreamClient,
+    site: &IshareSite,
+) -> Result<HashMap<String, IshareETFListItem>, Error> {
+    let url = format!(
+        "https://www.ishares.com{}/products/etf-investments",
+        site.path
+    );
+    let html = upstream.get_text(&url).await?;
     let document = Html::parse_document(&html);
 
     // The table we're looking for is in a noscript block.
@@ -89,7 +120,7 @@ async fn fetch_etf_list() -> Result<HashMap<String, IshareETFListItem>, Error> {
         .text()
         .next()
         .ok_or("Found a noscript block on the iShare ETFs page, but no text nodes inside.")?;
-    let noscript_fragment = Html::parse_fragment(&noscript_html);
+    let noscript_fragment = Html::parse_fragment(noscript_html);
 
     // Find the table of ETFs inside the noscript block
     // The table looks like the following, so we'll call next() on the iterator twice for each ETF
@@ -116,39 +147,53 @@ async fn fetch_etf_list() -> Result<HashMap<String, IshareETFListItem>, Error> {
         let ticker = ticker_elem.inner_html();
         etfs.insert(
             String::from(&ticker),
-            IshareETFListItem {
-                ticker: ticker,
-                name: name,
-                url: url,
-            },
+            IshareETFListItem { ticker, name, url },
         );
     }
     Ok(etfs)
 }
 
-#[derive(Debug, Deserialize)]
+/// A row of the holdings table, see `RawIshareHolding`
+#[derive(Debug)]
 struct IshareHolding {
+    ticker: String,
+    name: String,
+    asset_class: String,
+    /// Only equity funds have a sector column
+    sector: Option<String>,
+    market_value: f64,
+    weight: f64,
+    notional_value: Option<f64>,
+    shares: Option<f64>,
+    price: Option<f64>,
+    location: String,
+    exchange: String,
+    currency: String,
+    fx_rate: Option<f64>,
+    market_currency: String,
+}
+
+/// A row of the holdings table before its numbers are parsed with the site's `NumberFormat`
+#[derive(Debug, Deserialize)]
+struct RawIshareHolding {
     #[serde(rename = "Ticker")]
     ticker: String,
     #[serde(rename = "Name")]
     name: String,
     #[serde(rename = "Asset Class")]
     asset_class: String,
+    #[serde(rename = "Sector", default)]
+    sector: Option<String>,
     #[serde(rename = "Market Value")]
-    #[serde(with = "deserialize_formatted_floats")]
-    market_value: f64,
+    market_value: String,
     #[serde(rename = "Weight (%)")]
-    #[serde(with = "deserialize_formatted_floats")]
-    weight: f64,
+    weight: String,
     #[serde(rename = "Notional Value")]
-    #[serde(with = "deserialize_formatted_floats")]
-    notional_value: f64,
+    notional_value: String,
     #[serde(rename = "Shares")]
-    #[serde(with = "deserialize_formatted_floats")]
-    shares: f64,
+    shares: String,
     #[serde(rename = "Price")]
-    #[serde(with = "deserialize_formatted_floats")]
-    price: f64,
+    price: String,
     #[serde(rename = "Location")]
     location: String,
     #[serde(rename = "Exchange")]
@@ -156,30 +201,101 @@ struct IshareHolding {
     #[serde(rename = "Currency")]
     currency: String,
     #[serde(rename = "FX Rate")]
-    #[serde(with = "deserialize_formatted_floats")]
-    fx_rate: f64,
+    fx_rate: String,
     #[serde(rename = "Market Currency")]
     market_currency: String,
 }
 
-async fn fetch_holdings(etf_item: &IshareETFListItem) -> Result<ETF, Error> {
+impl RawIshareHolding {
+    /// Parse the numbers, any number that can't be parsed is an error.
+    fn parse(self, number_format: NumberFormat) -> Result<IshareHolding, String> {
+        let (holding, problems) = self.salvage(number_format)?;
+        match problems.into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(holding),
+        }
+    }
+
+    /// Parse as much of the row as possible. Optional numbers that can't be parsed are left empty
+    /// and described in the returned messages, broken market value or weight is an error.
+    fn salvage(self, number_format: NumberFormat) -> Result<(IshareHolding, Vec<String>), String> {
+        let required = |column: &str, value: &str| {
+            number_format
+                .parse_required(value)
+                .map_err(|err| format!("{}: {}", column, err))
+        };
+        let market_value = required("Market Value", &self.market_value)?;
+        let weight = required("Weight (%)", &self.weight)?;
+
+        let mut problems = Vec::new();
+        let mut optional = |column: &str, value: &str| match number_format.parse(value) {
+            Ok(number) => number,
+            Err(err) => {
+                problems.push(format!("{}: {}", column, err));
+                None
+            }
+        };
+        let notional_value = optional("Notional Value", &self.notional_value);
+        let shares = optional("Shares", &self.shares);
+        let price = optional("Price", &self.price);
+        let fx_rate = optional("FX Rate", &self.fx_rate);
+
+        let holding = IshareHolding {
+            ticker: self.ticker,
+            name: self.name,
+            asset_class: self.asset_class,
+            sector: self.sector,
+            market_value,
+            weight,
+            notional_value,
+            shares,
+            price,
+            location: self.location,
+            exchange: self.exchange,
+            currency: self.currency,
+            fx_rate,
+            market_currency: self.market_currency,
+        };
+        Ok((holding, problems))
+    }
+}
+
+async fn fetch_holdings(
+    upstream: &UpstreamClient,
+    site: &IshareSite,
+    etf_item: &IshareETFListItem,
+    ticker_mapping: &TickerMapping,
+    lenient_parsing: bool,
+) -> Result<ETF, Error> {
     let url = format!(
         "https://www.ishares.com{}/1467271812596.ajax?fileType=csv&dataType=fund",
         etf_item.url
     );
-    let csv = reqwest::get(url)
-        .await?
-        .text()
+    let csv = upstream
+        .get_text(&url)
         .await?
         .replace(|c: char| !c.is_ascii(), "");
+    parse_holdings(site, etf_item, &csv, ticker_mapping, lenient_parsing)
+}
+
+/// Parse the holdings CSV, it has an info table and a holdings table separated by a blank line.
+fn parse_holdings(
+    site: &IshareSite,
+    etf_item: &IshareETFListItem,
+    csv: &str,
+    ticker_mapping: &TickerMapping,
+    lenient_parsing: bool,
+) -> Result<ETF, Error> {
     let mut splitted_csv = csv.split("\n\n");
 
     let mut outstanding_shares = None;
     let mut last_update = None;
+    let info_lines;
     {
         let info_table = splitted_csv
             .next()
             .ok_or("Can't find iShare info table. CSV format must have changed.")?;
+        info_lines = info_table.lines().count() as u64;
         let mut reader = csv::ReaderBuilder::new()
             .has_headers(false)
             .flexible(true)
@@ -192,27 +308,95 @@ async fn fetch_holdings(etf_item: &IshareETFListItem) -> Result<ETF, Error> {
                     last_update = Some(row.get(1).unwrap().to_string());
                 }
                 if row.get(0).unwrap() == "Shares Outstanding" {
-                    outstanding_shares = Some(deserialize_formatted_floats::parse_formatted_float(
-                        row.get(1).unwrap(),
-                    )?);
+                    outstanding_shares =
+                        Some(site.number_format.parse_required(row.get(1).unwrap())?);
                 }
             }
         }
     }
 
     let mut holdings = Vec::new();
+    let mut unresolved_holdings = Vec::new();
+    let mut warnings = Vec::new();
     {
         let holdings_table = splitted_csv
             .next()
             .ok_or("Can't find iShare holdings table. CSV format must have changed.")?;
+        // Line numbers from the csv reader are relative to the holdings table, which starts after
+        // the info table and a blank line
+        let make_warning = |line: u64, message: String, skipped: bool| ParseWarning {
+            line: info_lines + 1 + line,
+            raw: holdings_table
+                .lines()
+                .nth((line as usize).saturating_sub(1))
+                .unwrap_or_default()
+                .to_string(),
+            message,
+            skipped,
+        };
+
         let mut reader = csv::ReaderBuilder::new().from_reader(holdings_table.as_bytes());
-        for record in reader.deserialize() {
-            let row: IshareHolding = record?;
-            let ticker = fully_qualified_ticker(&row.ticker, &row.exchange);
+        let headers = reader.headers()?.clone();
+        for record in reader.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) if lenient_parsing => {
+                    let line = err.position().map_or(0, |p| p.line());
+                    warnings.push(make_warning(line, err.to_string(), true));
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let line = record.position().map_or(0, |p| p.line());
+            let raw = match record.deserialize::<RawIshareHolding>(Some(&headers)) {
+                Ok(raw) => raw,
+                Err(err) if lenient_parsing => {
+                    warnings.push(make_warning(line, err.to_string(), true));
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let row = if lenient_parsing {
+                match raw.salvage(site.number_format) {
+                    Ok((row, problems)) => {
+                        if !problems.is_empty() {
+                            warnings.push(make_warning(line, problems.join("; "), false));
+                        }
+                        row
+                    }
+                    Err(message) => {
+                        warnings.push(make_warning(line, message, true));
+                        continue;
+                    }
+                }
+            } else {
+                raw.parse(site.number_format).map_err(|message| {
+                    format!(
+                        "Line {} of the holdings file: {}",
+                        info_lines + 1 + line,
+                        message
+                    )
+                })?
+            };
+            let symbols = vendor_symbols(ticker_mapping, &row.ticker, &row.exchange);
+            let ticker = match &symbols.yahoo {
+                Some(ticker) => ticker.clone(),
+                // Cash, futures and the like have "-" as their exchange, there's nothing to qualify
+                None if row.exchange == "-" => row.ticker,
+                None => {
+                    unresolved_holdings.push(UnresolvedHolding {
+                        ticker: row.ticker.clone(),
+                        name: row.name.clone(),
+                        exchange: row.exchange.clone(),
+                    });
+                    row.ticker
+                }
+            };
             holdings.push(Holding {
-                ticker: ticker,
+                ticker,
                 name: row.name,
                 asset_class: row.asset_class,
+                sector: row.sector.filter(|sector| sector != "-"),
                 market_value: row.market_value,
                 weight: row.weight,
                 notional_value: row.notional_value,
@@ -223,17 +407,184 @@ async fn fetch_holdings(etf_item: &IshareETFListItem) -> Result<ETF, Error> {
                 currency: row.currency,
                 fx_rate: row.fx_rate,
                 market_currency: row.market_currency,
+                symbols,
             })
         }
     }
     Ok(ETF {
+        schema_version: SCHEMA_VERSION,
         ticker: etf_item.ticker.clone(),
         name: etf_item.name.clone(),
+        currency: site.currency.to_string(),
         last_update: last_update
             .ok_or("No last update found in iShare info table. CSV format must have changed.")?,
         outstanding_shares: outstanding_shares.ok_or(
             "No outstanding shares found in iShare info table. CSV format must have changed.",
         )?,
-        holdings: holdings,
+        holdings,
+        unresolved_holdings,
+        warnings,
+        validation: ValidationReport::default(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOLDINGS_CSV: &str = "\
+Fund Holdings as of,\"Oct 15, 2021\"
+Shares Outstanding,\"1,000.00\"
+
+Ticker,Name,Sector,Asset Class,Market Value,Weight (%),Notional Value,Shares,Price,Location,Exchange,Currency,FX Rate,Market Currency
+\"AAPL\",\"APPLE INC\",\"Information Technology\",\"Equity\",\"1,234.50\",\"60.00\",\"1,234.50\",\"10.00\",\"123.45\",\"United States\",\"NASDAQ\",\"USD\",\"1.00\",\"USD\"
+\"USD\",\"USD CASH\",\"Cash and/or Derivatives\",\"Cash\",\"823.00\",\"40.00\",\"823.00\",\"823.00\",\"100.00\",\"United States\",\"-\",\"USD\",\"1.00\",\"USD\"
+";
+
+    fn etf_item() -> IshareETFListItem {
+        IshareETFListItem {
+            ticker: "TEST".to_string(),
+            name: "iShares Test ETF".to_string(),
+            url: "/us/products/1/test".to_string(),
+        }
+    }
+
+    fn parse(site: &IshareSite, csv: &str, lenient_parsing: bool) -> Result<ETF, Error> {
+        let mapping = TickerMapping::default();
+        parse_holdings(site, &etf_item(), csv, &mapping, lenient_parsing)
+    }
+
+    #[test]
+    fn parses_with_the_site_number_format() {
+        let etf = parse(&US_SITE, HOLDINGS_CSV, false).unwrap();
+        assert_eq!(etf.currency, "USD");
+        assert_eq!(etf.outstanding_shares, 1000.0);
+        assert_eq!(etf.holdings.len(), 2);
+        assert_eq!(etf.holdings[0].ticker, "AAPL");
+        assert_eq!(etf.holdings[0].market_value, 1234.5);
+        assert_eq!(etf.holdings[0].notional_value, Some(1234.5));
+    }
+
+    /// Rewrite the numbers of a holdings file with other separators, like a regional site would
+    fn localize(csv: &str, thousands: char, decimal: char) -> String {
+        csv.split('"')
+            .enumerate()
+            .map(|(i, cell)| {
+                let is_number = cell.starts_with(|c: char| c.is_ascii_digit())
+                    && cell
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || c == ',' || c == '.');
+                if i % 2 == 1 && is_number {
+                    cell.replace(',', "\u{0}")
+                        .replace('.', &decimal.to_string())
+                        .replace('\u{0}', &thousands.to_string())
+                } else {
+                    cell.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\"")
+    }
+
+    #[test]
+    fn regional_sites_parse_their_own_number_format() {
+        let de_site = IshareSite {
+            path: "/de",
+            currency: "EUR",
+            number_format: NumberFormat::European,
+        };
+        let ch_site = IshareSite {
+            path: "/ch",
+            currency: "CHF",
+            number_format: NumberFormat::Swiss,
+        };
+        let us = parse(&US_SITE, HOLDINGS_CSV, false).unwrap();
+        let de = parse(&de_site, &localize(HOLDINGS_CSV, '.', ','), false).unwrap();
+        let ch = parse(&ch_site, &localize(HOLDINGS_CSV, '\'', '.'), false).unwrap();
+
+        assert_eq!(de.currency, "EUR");
+        assert_eq!(ch.currency, "CHF");
+        for etf in [&de, &ch] {
+            assert_eq!(etf.outstanding_shares, us.outstanding_shares);
+            for (holding, expected) in etf.holdings.iter().zip(&us.holdings) {
+                assert_eq!(holding.market_value, expected.market_value);
+                assert_eq!(holding.weight, expected.weight);
+                assert_eq!(holding.notional_value, expected.notional_value);
+                assert_eq!(holding.shares, expected.shares);
+                assert_eq!(holding.price, expected.price);
+                assert_eq!(holding.fx_rate, expected.fx_rate);
+            }
+            assert!(etf.warnings.is_empty());
+        }
+    }
+
+    #[test]
+    fn european_site_rejects_english_numbers() {
+        let site = IshareSite {
+            number_format: NumberFormat::European,
+            ..US_SITE
+        };
+        let err = parse(&site, HOLDINGS_CSV, false).unwrap_err();
+        assert!(matches!(err, Error::Generic(message) if message.contains("1,000.00")));
+    }
+
+    #[test]
+    fn strict_parsing_points_at_the_bad_line() {
+        let csv = HOLDINGS_CSV.replace("\"123.45\"", "\"1,23.45\"");
+        let err = parse(&US_SITE, &csv, false).unwrap_err();
+        assert!(
+            matches!(&err, Error::Generic(message) if message.starts_with("Line 5 of the holdings file: Price")),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn lenient_parsing_skips_rows_without_a_weight_or_market_value() {
+        let csv = HOLDINGS_CSV
+            .replacen("\"1,234.50\"", "\"1,23.50\"", 1)
+            .replace("\"40.00\"", "\"forty\"");
+        let etf = parse(&US_SITE, &csv, true).unwrap();
+        assert!(etf.holdings.is_empty());
+        assert_eq!(etf.warnings.len(), 2);
+
+        let market_value = &etf.warnings[0];
+        assert_eq!(market_value.line, 5);
+        assert!(market_value.skipped);
+        assert!(market_value.raw.starts_with("\"AAPL\""));
+        assert!(
+            market_value.message.contains("1,23.50"),
+            "{}",
+            market_value.message
+        );
+
+        let weight = &etf.warnings[1];
+        assert_eq!(weight.line, 6);
+        assert!(weight.skipped);
+        assert!(weight.raw.starts_with("\"USD\""));
+        assert!(weight.message.contains("forty"), "{}", weight.message);
+    }
+
+    #[test]
+    fn lenient_parsing_skips_malformed_csv_records() {
+        let csv = format!("{}\"MSFT\",\"MICROSOFT CORP\"\n", HOLDINGS_CSV);
+        assert!(parse(&US_SITE, &csv, false).is_err());
+
+        let etf = parse(&US_SITE, &csv, true).unwrap();
+        assert_eq!(etf.holdings.len(), 2);
+        assert_eq!(etf.warnings.len(), 1);
+        assert_eq!(etf.warnings[0].line, 7);
+        assert_eq!(etf.warnings[0].raw, "\"MSFT\",\"MICROSOFT CORP\"");
+        assert!(etf.warnings[0].skipped);
+    }
+
+    #[test]
+    fn lenient_parsing_empties_bad_optional_numbers() {
+        let csv = HOLDINGS_CSV.replace("\"123.45\"", "\"1,23.45\"");
+        let etf = parse(&US_SITE, &csv, true).unwrap();
+        assert_eq!(etf.holdings[0].price, None);
+        assert_eq!(etf.warnings.len(), 1);
+        assert_eq!(etf.warnings[0].line, 5);
+        assert!(!etf.warnings[0].skipped);
+    }
+}
@@ -2,65 +2,742 @@
 //!
 //! ETFHoldings provides an interface to discover supported ETFs and fetch their details.
 
-use std::collections::HashMap;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 use tokio::sync::{Mutex, RwLock};
+use tracing::{debug, error, info, info_span, instrument, Instrument};
 
-mod deserialize_formatted_floats;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod breakdown;
+mod bulk;
+mod export;
+mod exposure;
+mod fx;
+mod holding_index;
 mod ishares;
+mod look_through;
+mod metrics;
+#[cfg(test)]
+mod mock;
+mod nav;
+pub mod numbers;
+mod overlap;
+mod portfolio;
+mod schema;
+mod symbology;
 mod ticker;
 mod types;
+mod upstream;
+pub mod validation;
+pub use breakdown::breakdown;
+pub use export::{export, export_rows};
+pub use exposure::{exposure, holding_exposure, is_derivative};
+pub use fx::{convert_etf, FallbackFxRates, FundFxRates, FxRateSource};
+use holding_index::HoldingIndex;
 use ishares::Ishare;
-pub use types::{ETFListItem, Error, FundManager, ETF};
+pub use metrics::{concentration, etf_metrics};
+pub use nav::{implied_nav, net_assets, premium_discount};
+pub use overlap::overlap;
+pub use schema::{etf_json_schema, etf_list_item_json_schema};
+pub use symbology::{security_identifier, vendor_symbols};
+pub use ticker::{TickerMapping, UnknownExchange, VendorCodes};
+pub use types::{
+    BreakdownBucket, BreakdownBy, BulkFetch, BulkFetchOptions, CommonHolding, ConcentrationMetrics,
+    ETFListItem, ETFMetrics, ETFPosition, Error, ExportFormat, ExportRow, Exposure,
+    ExposureSummary, FetchFailure, FundManager, FundManagerStatus, Holding, HoldingExposure,
+    LookThrough, LookThroughHolding, ManagerContext, Overlap, ParseWarning, Portfolio,
+    PortfolioHolding, PortfolioLookThrough, PortfolioPosition, PositionSize, RetryPolicy,
+    SharedTickerMapping, SnapshotInfo, Symbols, UnexpandedFund, UnexpandedReason, UnmappedExchange,
+    UnresolvedHolding, UpstreamObserver, ValidationIssue, ValidationReport, ETF, SCHEMA_VERSION,
+};
+pub use upstream::UpstreamClient;
+
+/// Options for creating an instance of `ETFHoldings`.
+#[derive(Debug, Clone, Default)]
+pub struct ETFHoldingsOptions {
+    /// JSON file with extra ticker mappings (see `TickerMapping`), it's read again by
+    /// `ETFHoldings::reload_ticker_mapping`.
+    pub ticker_mapping_path: Option<PathBuf>,
+    /// Skip or partially fill bad rows in holdings files instead of failing the whole ETF. The
+    /// problems are reported in `ETF::warnings`.
+    pub lenient_parsing: bool,
+    /// Timeout of each request to a fund manager, `DEFAULT_REQUEST_TIMEOUT` if `None`
+    pub request_timeout: Option<Duration>,
+    /// How failed requests to fund managers are retried
+    pub retry_policy: RetryPolicy,
+    /// Most requests per second to each fund manager's host, `DEFAULT_REQUESTS_PER_SECOND` if
+    /// `None`. `f64::INFINITY` turns the limit off.
+    pub requests_per_second: Option<f64>,
+    /// How long a fetched ETF is served before it's fetched again, `DEFAULT_SNAPSHOT_TTL` if `None`
+    pub snapshot_ttl: Option<Duration>,
+    /// Gets told about every request to fund managers
+    pub upstream_observer: Option<Arc<dyn UpstreamObserver>>,
+}
+
+/// Timeout of requests to fund managers unless set in `ETFHoldingsOptions`
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Requests per second to each fund manager's host unless set in `ETFHoldingsOptions`
+pub const DEFAULT_REQUESTS_PER_SECOND: f64 = 2.0;
+
+/// How long fetched ETFs are kept unless set in `ETFHoldingsOptions`. Fund managers publish new
+/// holdings once a day.
+pub const DEFAULT_SNAPSHOT_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// An ETF and when it was fetched
+struct FetchedETF {
+    etf: ETF,
+    fetched_at: Instant,
+}
+
+/// The validation result of the latest snapshot of an ETF
+struct ValidatedSnapshot {
+    last_update: String,
+    holdings_count: usize,
+    report: ValidationReport,
+}
+
+/// A fund manager that was set up, or the reason it couldn't be
+type ManagerSetup = Result<Arc<dyn FundManager>, Error>;
+
+/// Sets up a fund manager, kept to try again if it fails
+pub(crate) type ManagerConstructor =
+    Arc<dyn Fn(ManagerContext) -> BoxFuture<'static, ManagerSetup> + Send + Sync>;
+
+/// A fetch from a fund manager that several requests can wait for
+type SharedFetch = Shared<BoxFuture<'static, Result<ETF, Error>>>;
 
 /// An instance of `ETFHoldings` can list supported ETFs and fetch ETF details.
 pub struct ETFHoldings {
-    etf_to_manager: RwLock<HashMap<String, Arc<Mutex<dyn FundManager>>>>,
+    /// Fund managers and whether they could be set up
+    fund_managers: RwLock<Vec<FundManagerStatus>>,
+    /// How to set up each fund manager, by name
+    constructors: Vec<(String, ManagerConstructor)>,
+    /// Settings handed to fund managers when they're set up
+    context: ManagerContext,
+    etf_to_manager: RwLock<HashMap<String, Arc<dyn FundManager>>>,
+    /// Fetched ETFs, until they're older than `snapshot_ttl` or the ticker mapping is reloaded
+    fetched_etfs: RwLock<HashMap<String, FetchedETF>>,
+    snapshot_ttl: Duration,
+    /// Fetches in progress, concurrent requests for the same ticker share one fetch
+    in_flight: Mutex<HashMap<String, SharedFetch>>,
     etf_list: RwLock<Vec<ETFListItem>>,
+    /// Exchange names without a suffix mapping, and the ETFs they were seen in
+    unmapped_exchanges: RwLock<BTreeMap<String, BTreeSet<String>>>,
+    /// Latest validated snapshot of each ETF, to compare new snapshots against
+    validated_snapshots: RwLock<HashMap<String, ValidatedSnapshot>>,
+    /// Which fetched ETFs hold which securities
+    holding_index: RwLock<HoldingIndex>,
+    ticker_mapping: SharedTickerMapping,
+    /// File with extra ticker mappings to load on top of the built-in ones
+    ticker_mapping_path: Option<PathBuf>,
 }
 
 impl ETFHoldings {
     /// Creates an instance of ETFHoldings. This includes network calls to find an up to date list
-    /// of ETFs.
-    pub async fn new() -> ETFHoldings {
-        let mut etf_to_manager = HashMap::<String, Arc<Mutex<dyn FundManager>>>::new();
-        let mut etf_list = Vec::<ETFListItem>::new();
-
-        let manager_constructors = [Ishare::new];
-        for manager_constructor in manager_constructors.iter() {
-            match manager_constructor().await {
-                Ok(manager) => {
-                    let etfs = manager.etfs_under_management();
-                    let manager_ref = Arc::new(Mutex::new(manager));
-                    for etf in etfs {
-                        etf_to_manager.insert(etf.ticker.clone(), manager_ref.clone());
-                        etf_list.push(etf.clone());
-                    }
-                }
-                Err(e) => eprintln!("{:?}", e),
+    /// of ETFs. Fails if the HTTP client can't be set up.
+    pub async fn new() -> Result<ETFHoldings, Error> {
+        Self::with_options(ETFHoldingsOptions::default()).await
+    }
+
+    /// Creates an instance of ETFHoldings with custom options. Fails if the ticker mapping file
+    /// can't be loaded or the HTTP client can't be set up.
+    pub async fn with_options(options: ETFHoldingsOptions) -> Result<ETFHoldings, Error> {
+        let ticker_mapping = match &options.ticker_mapping_path {
+            Some(path) => TickerMapping::from_file(path)?,
+            None => TickerMapping::default(),
+        };
+        let upstream = UpstreamClient::new(
+            options.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT),
+            options.retry_policy,
+            Some(
+                options
+                    .requests_per_second
+                    .unwrap_or(DEFAULT_REQUESTS_PER_SECOND),
+            ),
+            options.upstream_observer,
+        )?;
+        let context = ManagerContext {
+            ticker_mapping: Arc::new(RwLock::new(ticker_mapping)),
+            upstream: Arc::new(upstream),
+            lenient_parsing: options.lenient_parsing,
+        };
+        let snapshot_ttl = options.snapshot_ttl.unwrap_or(DEFAULT_SNAPSHOT_TTL);
+        Ok(Self::with_context(context, options.ticker_mapping_path, snapshot_ttl).await)
+    }
+
+    async fn with_context(
+        context: ManagerContext,
+        ticker_mapping_path: Option<PathBuf>,
+        snapshot_ttl: Duration,
+    ) -> ETFHoldings {
+        let ishares: ManagerConstructor = Arc::new(|context| {
+            async move {
+                let manager = Ishare::new(context).await?;
+                Ok(Arc::new(manager) as Arc<dyn FundManager>)
             }
+            .boxed()
+        });
+        Self::with_constructors(
+            context,
+            ticker_mapping_path,
+            snapshot_ttl,
+            vec![("iShares".to_string(), ishares)],
+        )
+        .await
+    }
+
+    /// Creates an instance and sets up the fund managers. Fund managers that fail are listed with
+    /// the error and can be tried again with `retry_failed_managers`.
+    async fn with_constructors(
+        context: ManagerContext,
+        ticker_mapping_path: Option<PathBuf>,
+        snapshot_ttl: Duration,
+        constructors: Vec<(String, ManagerConstructor)>,
+    ) -> ETFHoldings {
+        let etf_holdings = ETFHoldings {
+            fund_managers: RwLock::new(Vec::new()),
+            constructors,
+            context: context.clone(),
+            etf_to_manager: RwLock::new(HashMap::new()),
+            fetched_etfs: RwLock::new(HashMap::new()),
+            snapshot_ttl,
+            in_flight: Mutex::new(HashMap::new()),
+            etf_list: RwLock::new(Vec::new()),
+            unmapped_exchanges: RwLock::new(BTreeMap::new()),
+            validated_snapshots: RwLock::new(HashMap::new()),
+            holding_index: RwLock::new(HoldingIndex::default()),
+            ticker_mapping: context.ticker_mapping,
+            ticker_mapping_path,
+        };
+        for (name, constructor) in &etf_holdings.constructors {
+            let setup = constructor(etf_holdings.context.clone()).await;
+            etf_holdings.add_manager(name, setup).await;
         }
+        etf_holdings
+    }
 
-        ETFHoldings {
-            etf_to_manager: RwLock::new(etf_to_manager),
-            etf_list: RwLock::new(etf_list),
+    /// Try again to set up fund managers that failed, e.g. because their website was down. Their
+    /// ETFs are supported from then on.
+    pub async fn retry_failed_managers(&self) {
+        for (name, constructor) in &self.constructors {
+            let failed = self
+                .fund_managers
+                .read()
+                .await
+                .iter()
+                .any(|manager| &manager.name == name && manager.error.is_some());
+            if failed {
+                info!(manager = %name, "Retrying fund manager setup");
+                let setup = constructor(self.context.clone()).await;
+                self.add_manager(name, setup).await;
+            }
+        }
+    }
+
+    /// Record the outcome of setting up a fund manager, and list its ETFs if it worked.
+    async fn add_manager(&self, name: &str, setup: ManagerSetup) {
+        let mut fund_managers = self.fund_managers.write().await;
+        let position = fund_managers
+            .iter()
+            .position(|manager| manager.name == name);
+        if let Some(i) = position {
+            // Set up in the meantime by a concurrent retry
+            if fund_managers[i].error.is_none() {
+                return;
+            }
+        }
+
+        let status = match setup {
+            Ok(manager) => {
+                let etfs = manager.etfs_under_management();
+                let mut etf_to_manager = self.etf_to_manager.write().await;
+                let mut etf_list = self.etf_list.write().await;
+                for etf in &etfs {
+                    etf_to_manager.insert(etf.ticker.clone(), manager.clone());
+                    etf_list.push(etf.clone());
+                }
+                info!(manager = %name, etfs = etfs.len(), "Fund manager set up");
+                FundManagerStatus {
+                    name: name.to_string(),
+                    etfs: etfs.len(),
+                    error: None,
+                }
+            }
+            Err(e) => {
+                error!(manager = %name, error = ?e, "Failed to set up fund manager");
+                FundManagerStatus {
+                    name: name.to_string(),
+                    etfs: 0,
+                    error: Some(format!("{:?}", e)),
+                }
+            }
+        };
+        match position {
+            Some(i) => fund_managers[i] = status,
+            None => fund_managers.push(status),
         }
     }
 
     /// Returns a list of supported ETFs.
     pub async fn etf_list(&self) -> Vec<ETFListItem> {
+        self.etf_list.read().await.to_vec()
+    }
+
+    /// Returns the fund managers and how many ETFs each supports. Fund managers that couldn't be
+    /// set up (e.g. because their website was down) are included with the error, see
+    /// `retry_failed_managers`.
+    pub async fn fund_managers(&self) -> Vec<FundManagerStatus> {
+        self.fund_managers.read().await.clone()
+    }
+
+    /// Returns the latest snapshot of every ETF fetched so far.
+    pub async fn snapshots(&self) -> Vec<SnapshotInfo> {
+        self.validated_snapshots
+            .read()
+            .await
+            .iter()
+            .map(|(ticker, snapshot)| SnapshotInfo {
+                ticker: ticker.clone(),
+                last_update: snapshot.last_update.clone(),
+                holdings_count: snapshot.holdings_count,
+            })
+            .collect()
+    }
+
+    /// Returns supported ETFs with the query in their ticker or name, ignoring case.
+    pub async fn search(&self, query: &str) -> Vec<ETFListItem> {
         self.etf_list
             .read()
             .await
             .iter()
-            .map(|s| s.clone())
+            .filter(|etf| etf.matches(query))
+            .cloned()
             .collect()
     }
 
     /// Fetch ETF details and holdings for a supported ETF.
+    ///
+    /// The holdings are sanity checked (see `validation::validate`) and the result is attached as
+    /// `ETF::validation`.
+    #[instrument(skip(self))]
     pub async fn etf_details(&self, ticker: &String) -> Result<ETF, Error> {
-        let manager_map = self.etf_to_manager.read().await;
-        let mut manager = manager_map.get(ticker).ok_or(Error::NotFound)?.lock().await;
-        manager.etf_details(ticker).await
+        self.fetch_etf(ticker).await
+    }
+
+    /// Returns a fetched ETF, or fetches it from its fund manager if it hasn't been fetched in the
+    /// last `snapshot_ttl`.
+    ///
+    /// Different tickers are fetched in parallel. Concurrent requests for a ticker that's already
+    /// being fetched wait for that fetch instead of starting another one.
+    async fn fetch_etf(&self, ticker: &String) -> Result<ETF, Error> {
+        if let Some(etf) = self.cached_etf(ticker).await {
+            debug!(ticker = %ticker, "ETF cache hit");
+            return Ok(etf);
+        }
+
+        let fetch = {
+            let mut in_flight = self.in_flight.lock().await;
+            // Another request may have finished fetching it since the check above, the ETF is
+            // cached while holding this lock
+            if let Some(etf) = self.cached_etf(ticker).await {
+                debug!(ticker = %ticker, "ETF fetched while waiting for the lock");
+                return Ok(etf);
+            }
+            match in_flight.get(ticker) {
+                Some(fetch) => {
+                    debug!(ticker = %ticker, "ETF already being fetched, waiting for it");
+                    fetch.clone()
+                }
+                None => {
+                    debug!(ticker = %ticker, "ETF cache miss");
+                    let manager = self
+                        .etf_to_manager
+                        .read()
+                        .await
+                        .get(ticker)
+                        .ok_or(Error::NotFound)?
+                        .clone();
+                    let span = info_span!("fund_manager_fetch", ticker = %ticker, manager = manager.host());
+                    let owned_ticker = ticker.clone();
+                    let fetch = async move {
+                        let start = Instant::now();
+                        let result = manager.etf_details(&owned_ticker).await;
+                        let duration_ms = start.elapsed().as_millis() as u64;
+                        match &result {
+                            Ok(etf) => info!(
+                                duration_ms,
+                                holdings = etf.holdings.len(),
+                                warnings = etf.warnings.len(),
+                                "Fetched ETF"
+                            ),
+                            Err(err) => error!(duration_ms, error = ?err, "Failed to fetch ETF"),
+                        }
+                        result
+                    }
+                    .instrument(span)
+                    .boxed()
+                    .shared();
+                    in_flight.insert(ticker.clone(), fetch.clone());
+                    fetch
+                }
+            }
+        };
+        let result = fetch.clone().await;
+
+        // The first request to finish validates, indexes and caches the result, unless the ticker
+        // mapping was reloaded while fetching. Doing that under the in-flight lock means later
+        // requests don't fetch again.
+        let mut in_flight = self.in_flight.lock().await;
+        let first = in_flight.get(ticker).is_some_and(|f| f.ptr_eq(&fetch));
+        if first {
+            in_flight.remove(ticker);
+        }
+        let mut etf = result?;
+        if first {
+            self.add_snapshot(&mut etf).await;
+            let fetched = FetchedETF {
+                etf: etf.clone(),
+                fetched_at: Instant::now(),
+            };
+            self.fetched_etfs
+                .write()
+                .await
+                .insert(ticker.clone(), fetched);
+        } else if let Some(cached) = self.cached_etf(ticker).await {
+            // Another request waiting for the same fetch got there first
+            return Ok(cached);
+        } else {
+            // Fetched with a ticker mapping that was reloaded since, it's not kept
+            etf.validation = validation::validate(&etf, None);
+        }
+        Ok(etf)
+    }
+
+    /// Validate a newly fetched ETF and add it to the holding index and unmapped exchanges.
+    async fn add_snapshot(&self, etf: &mut ETF) {
+        etf.validation = self.validate(etf).await;
+        self.holding_index.write().await.update(etf);
+
+        if !etf.unresolved_holdings.is_empty() {
+            let mut unmapped_exchanges = self.unmapped_exchanges.write().await;
+            for holding in &etf.unresolved_holdings {
+                unmapped_exchanges
+                    .entry(holding.exchange.clone())
+                    .or_default()
+                    .insert(etf.ticker.clone());
+            }
+        }
+    }
+
+    /// Returns a fetched ETF unless it's older than `snapshot_ttl`.
+    async fn cached_etf(&self, ticker: &str) -> Option<ETF> {
+        self.fetched_etfs
+            .read()
+            .await
+            .get(ticker)
+            .filter(|fetched| fetched.fetched_at.elapsed() < self.snapshot_ttl)
+            .map(|fetched| fetched.etf.clone())
+    }
+
+    /// Validate a snapshot of an ETF against the previous snapshot. Validating the same snapshot
+    /// again returns the same report.
+    async fn validate(&self, etf: &ETF) -> ValidationReport {
+        let mut validated_snapshots = self.validated_snapshots.write().await;
+        let previous = validated_snapshots.get(&etf.ticker);
+        if let Some(previous) = previous {
+            if previous.last_update == etf.last_update {
+                return previous.report.clone();
+            }
+        }
+
+        let report = validation::validate(etf, previous.map(|p| p.holdings_count));
+        validated_snapshots.insert(
+            etf.ticker.clone(),
+            ValidatedSnapshot {
+                last_update: etf.last_update.clone(),
+                holdings_count: etf.holdings.len(),
+                report: report.clone(),
+            },
+        );
+        report
+    }
+
+    /// Returns the ETFs holding a security and at what weight, largest weight first.
+    ///
+    /// The security is identified by any of its qualified vendor symbols (see `Symbols`), e.g.
+    /// `BHP.AX` or `BHP AU Equity` but not `BHP`. Cash and derivatives aren't indexed.
+    ///
+    /// The index is only fed by ETFs this instance has fetched, on demand with `etf_details` or in
+    /// bulk with `etf_details_many`/`etf_details_all` (which go through `etf_details`). ETFs that
+    /// haven't been fetched yet are missing, fetch them all first for a complete answer.
+    pub async fn etfs_holding(&self, identifier: &str) -> Vec<ETFPosition> {
+        self.holding_index.read().await.lookup(identifier)
+    }
+
+    /// Returns exchange names seen in fetched ETFs that we can't qualify tickers for yet.
+    ///
+    /// Holdings on these exchanges end up in `ETF::unresolved_holdings`, so this is the list of
+    /// exchanges to add a suffix mapping for next.
+    pub async fn unmapped_exchanges(&self) -> Vec<UnmappedExchange> {
+        self.unmapped_exchanges
+            .read()
+            .await
+            .iter()
+            .map(|(exchange, etfs)| UnmappedExchange {
+                exchange: exchange.clone(),
+                etfs: etfs.iter().cloned().collect(),
+            })
+            .collect()
+    }
+
+    /// Read the ticker mapping file again and use it for ETFs fetched from now on.
+    ///
+    /// Previously fetched ETFs are forgotten so their tickers get qualified with the new mapping,
+    /// along with everything derived from them (holding index, validated snapshots and unmapped
+    /// exchanges). If the file is invalid the current mapping is kept and the error is returned.
+    pub async fn reload_ticker_mapping(&self) -> Result<(), Error> {
+        let ticker_mapping = match &self.ticker_mapping_path {
+            Some(path) => TickerMapping::from_file(path)?,
+            None => TickerMapping::default(),
+        };
+        *self.ticker_mapping.write().await = ticker_mapping;
+
+        self.fetched_etfs.write().await.clear();
+        self.in_flight.lock().await.clear();
+        self.unmapped_exchanges.write().await.clear();
+        *self.holding_index.write().await = HoldingIndex::default();
+        self.validated_snapshots.write().await.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockManager};
+
+    fn aaa_manager(delay: Duration) -> Arc<MockManager> {
+        let etf = mock::equity_etf("AAA", &[("X", 100.0)]);
+        Arc::new(MockManager::new(vec![etf]).with_delay(delay))
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_requests_fetch_once() {
+        let manager = aaa_manager(Duration::from_millis(50));
+        let etf_holdings = Arc::new(mock::etf_holdings(vec![manager.clone()]).await);
+        let requests = (0..16).map(|_| {
+            let etf_holdings = etf_holdings.clone();
+            tokio::spawn(async move { etf_holdings.etf_details(&"AAA".to_string()).await })
+        });
+        for result in futures::future::join_all(requests).await {
+            assert_eq!(result.unwrap().unwrap().ticker, "AAA");
+        }
+        etf_holdings.etf_details(&"AAA".to_string()).await.unwrap();
+        assert_eq!(manager.calls("AAA"), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn every_request_gets_the_validated_etf() {
+        let etf = mock::equity_etf("AAA", &[("X", 50.0), ("Y", 40.0)]);
+        let manager = Arc::new(MockManager::new(vec![etf]).with_delay(Duration::from_millis(50)));
+        let etf_holdings = Arc::new(mock::etf_holdings(vec![manager.clone()]).await);
+        let requests = (0..8).map(|_| {
+            let etf_holdings = etf_holdings.clone();
+            tokio::spawn(async move { etf_holdings.etf_details(&"AAA".to_string()).await })
+        });
+        let mut etfs = Vec::new();
+        for result in futures::future::join_all(requests).await {
+            etfs.push(result.unwrap().unwrap());
+        }
+        // Cache hit
+        etfs.push(etf_holdings.etf_details(&"AAA".to_string()).await.unwrap());
+
+        assert_eq!(manager.calls("AAA"), 1);
+        for etf in etfs {
+            assert!(matches!(
+                etf.validation.issues[..],
+                [ValidationIssue::WeightSum { .. }]
+            ));
+        }
+        assert_eq!(etf_holdings.etfs_holding("X").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn request_missing_the_cache_while_a_fetch_finishes_waits_for_it() {
+        let manager = aaa_manager(Duration::from_millis(100));
+        let etf_holdings = Arc::new(mock::etf_holdings(vec![manager.clone()]).await);
+        let request = |etf_holdings: Arc<ETFHoldings>| {
+            tokio::spawn(async move { etf_holdings.etf_details(&"AAA".to_string()).await })
+        };
+
+        let first = request(etf_holdings.clone());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // Hold the lock so the first request finishes its fetch and queues up to cache the ETF,
+        // then the second request misses the cache and queues up behind it
+        let in_flight = etf_holdings.in_flight.lock().await;
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let second = request(etf_holdings.clone());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(in_flight);
+
+        first.await.unwrap().unwrap();
+        second.await.unwrap().unwrap();
+        assert_eq!(manager.calls("AAA"), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn different_tickers_are_fetched_in_parallel() {
+        let manager = Arc::new(
+            MockManager::new(vec![
+                mock::equity_etf("AAA", &[("X", 100.0)]),
+                mock::equity_etf("BBB", &[("Y", 100.0)]),
+            ])
+            .with_delay(Duration::from_millis(200)),
+        );
+        let etf_holdings = mock::etf_holdings(vec![manager.clone()]).await;
+        let (aaa, bbb) = ("AAA".to_string(), "BBB".to_string());
+        let start = Instant::now();
+        let (a, b) = tokio::join!(
+            etf_holdings.etf_details(&aaa),
+            etf_holdings.etf_details(&bbb)
+        );
+        assert!(a.is_ok() && b.is_ok());
+        assert!(start.elapsed() < Duration::from_millis(400));
+        assert_eq!(manager.calls("AAA"), 1);
+        assert_eq!(manager.calls("BBB"), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_fetches_are_not_cached() {
+        let manager = Arc::new(MockManager::new(Vec::new()).failing("AAA"));
+        let etf_holdings = mock::etf_holdings(vec![manager.clone()]).await;
+        let ticker = "AAA".to_string();
+        assert!(etf_holdings.etf_details(&ticker).await.is_err());
+        assert!(etf_holdings.etf_details(&ticker).await.is_err());
+        assert_eq!(manager.calls("AAA"), 2);
+    }
+
+    #[tokio::test]
+    async fn expired_snapshots_are_fetched_again_and_validated() {
+        let manager = Arc::new(MockManager::new(vec![mock::equity_etf(
+            "AAA",
+            &[("W", 25.0), ("X", 25.0), ("Y", 25.0), ("Z", 25.0)],
+        )]));
+        let etf_holdings =
+            mock::etf_holdings_with_ttl(vec![manager.clone()], Duration::from_millis(50)).await;
+        let ticker = "AAA".to_string();
+        assert!(etf_holdings
+            .etf_details(&ticker)
+            .await
+            .unwrap()
+            .validation
+            .is_ok());
+
+        let mut newer = mock::equity_etf("AAA", &[("X", 50.0), ("Y", 50.0)]);
+        newer.last_update = "Oct 18, 2021".to_string();
+        manager.publish(newer);
+        assert_eq!(
+            etf_holdings
+                .etf_details(&ticker)
+                .await
+                .unwrap()
+                .holdings
+                .len(),
+            4
+        );
+        assert_eq!(manager.calls("AAA"), 1);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let etf = etf_holdings.etf_details(&ticker).await.unwrap();
+        assert_eq!(manager.calls("AAA"), 2);
+        assert_eq!(etf.last_update, "Oct 18, 2021");
+        assert!(matches!(
+            etf.validation.issues[..],
+            [ValidationIssue::HoldingsCountDrop {
+                previous: 4,
+                current: 2
+            }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn reloading_the_mapping_fetches_again() {
+        let manager = aaa_manager(Duration::ZERO);
+        let etf_holdings = mock::etf_holdings(vec![manager.clone()]).await;
+        let ticker = "AAA".to_string();
+        etf_holdings.etf_details(&ticker).await.unwrap();
+        etf_holdings.reload_ticker_mapping().await.unwrap();
+        etf_holdings.etf_details(&ticker).await.unwrap();
+        assert_eq!(manager.calls("AAA"), 2);
+    }
+
+    #[tokio::test]
+    async fn reloading_the_mapping_forgets_derived_state() {
+        let etf_holdings = mock::etf_holdings(vec![aaa_manager(Duration::ZERO)]).await;
+        etf_holdings.etf_details(&"AAA".to_string()).await.unwrap();
+        assert_eq!(etf_holdings.etfs_holding("X").await.len(), 1);
+        assert_eq!(etf_holdings.snapshots().await.len(), 1);
+
+        etf_holdings.reload_ticker_mapping().await.unwrap();
+        assert!(etf_holdings.etfs_holding("X").await.is_empty());
+        assert!(etf_holdings.snapshots().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unknown_tickers_are_not_found() {
+        let etf_holdings = mock::etf_holdings(vec![Arc::new(MockManager::new(Vec::new()))]).await;
+        let result = etf_holdings.etf_details(&"ZZZ".to_string()).await;
+        assert!(matches!(result, Err(Error::NotFound)));
+    }
+
+    #[tokio::test]
+    async fn failed_managers_are_set_up_on_retry() {
+        let attempts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let constructor: ManagerConstructor = {
+            let attempts = attempts.clone();
+            Arc::new(move |_| {
+                let attempt = attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                async move {
+                    if attempt == 0 {
+                        return Err(Error::from("Website is down"));
+                    }
+                    Ok(aaa_manager(Duration::ZERO) as Arc<dyn FundManager>)
+                }
+                .boxed()
+            })
+        };
+        let etf_holdings = ETFHoldings::with_constructors(
+            mock::context(),
+            None,
+            DEFAULT_SNAPSHOT_TTL,
+            vec![("Mock".to_string(), constructor)],
+        )
+        .await;
+        let aaa = "AAA".to_string();
+        assert!(etf_holdings.etf_list().await.is_empty());
+        assert!(etf_holdings.fund_managers().await[0].error.is_some());
+        assert!(matches!(
+            etf_holdings.etf_details(&aaa).await,
+            Err(Error::NotFound)
+        ));
+
+        etf_holdings.retry_failed_managers().await;
+        let fund_managers = etf_holdings.fund_managers().await;
+        assert_eq!(fund_managers.len(), 1);
+        assert_eq!(fund_managers[0].error, None);
+        assert_eq!(fund_managers[0].etfs, 1);
+        assert_eq!(etf_holdings.etf_list().await.len(), 1);
+        etf_holdings.etf_details(&aaa).await.unwrap();
+
+        // Fund managers that work aren't set up again
+        etf_holdings.retry_failed_managers().await;
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(etf_holdings.etf_list().await.len(), 1);
     }
 }
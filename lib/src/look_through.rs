@@ -0,0 +1,284 @@
+//! Expand holdings that are ETFs themselves into their underlying holdings
+
+use std::collections::HashMap;
+
+use crate::symbology::security_identifier;
+use crate::types::{
+    Error, Holding, LookThrough, LookThroughHolding, UnexpandedFund, UnexpandedReason, ETF,
+};
+use crate::ETFHoldings;
+
+/// What underlying holdings are added up by. Securities are matched by their identifier, bare
+/// tickers aren't unique across exchanges. Cash and derivatives don't have one, they're only
+/// added up when they look the same.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub(crate) enum HoldingKey {
+    Security(String),
+    Other {
+        ticker: String,
+        name: String,
+        asset_class: String,
+    },
+}
+
+impl HoldingKey {
+    pub(crate) fn of(holding: &LookThroughHolding) -> HoldingKey {
+        match &holding.identifier {
+            Some(identifier) => HoldingKey::Security(identifier.clone()),
+            None => HoldingKey::Other {
+                ticker: holding.ticker.clone(),
+                name: holding.name.clone(),
+                asset_class: holding.asset_class.clone(),
+            },
+        }
+    }
+}
+
+/// A fund waiting to be expanded
+struct PendingFund {
+    etf: ETF,
+    /// Weight (%) of this fund in the top level ETF
+    weight: f64,
+    /// Tickers of the funds from the top level ETF down to (and including) this one
+    path: Vec<String>,
+}
+
+impl ETFHoldings {
+    /// Look through an ETF's holdings, expanding holdings that are supported ETFs into their own
+    /// holdings with weights multiplied through. Only holdings listed on a mapped US exchange are
+    /// taken for supported ETFs, an `IVV` elsewhere is a different security.
+    ///
+    /// Funds nested deeper than `max_depth` levels (the top level ETF is depth 0), funds that
+    /// hold themselves somewhere down the chain and nested funds that can't be fetched are kept as
+    /// opaque holdings and reported in `LookThrough::unexpanded`. Only failing to fetch the top
+    /// level ETF is an error.
+    pub async fn look_through(
+        &self,
+        ticker: &String,
+        max_depth: usize,
+    ) -> Result<LookThrough, Error> {
+        let etf = self.etf_details(ticker).await?;
+        let mut pending = vec![PendingFund {
+            path: vec![etf.ticker.clone()],
+            etf,
+            weight: 100.0,
+        }];
+        let mut holdings: HashMap<HoldingKey, LookThroughHolding> = HashMap::new();
+        let mut unexpanded = Vec::new();
+
+        while let Some(fund) = pending.pop() {
+            for holding in fund.etf.holdings {
+                let weight = fund.weight * holding.weight / 100.0;
+
+                let identifier = security_identifier(&holding).map(str::to_string);
+                if let Some(fund_ticker) = self.supported_fund(&holding).await {
+                    let reason = {
+                        if fund.path.contains(&fund_ticker) {
+                            UnexpandedReason::Cycle
+                        } else if fund.path.len() > max_depth {
+                            UnexpandedReason::MaxDepth
+                        } else {
+                            match self.etf_details(&fund_ticker).await {
+                                Ok(etf) => {
+                                    let mut path = fund.path.clone();
+                                    path.push(fund_ticker);
+                                    pending.push(PendingFund { etf, weight, path });
+                                    continue;
+                                }
+                                Err(err) => UnexpandedReason::FetchFailed {
+                                    error: format!("{:?}", err),
+                                },
+                            }
+                        }
+                    };
+                    unexpanded.push(UnexpandedFund {
+                        ticker: fund_ticker,
+                        path: fund.path.clone(),
+                        reason,
+                    });
+                }
+
+                let underlying = LookThroughHolding {
+                    ticker: holding.ticker,
+                    identifier,
+                    name: holding.name,
+                    asset_class: holding.asset_class,
+                    weight: 0.0,
+                    via: Vec::new(),
+                };
+                let entry = holdings
+                    .entry(HoldingKey::of(&underlying))
+                    .or_insert(underlying);
+                entry.weight += weight;
+                // Direct holdings of the top level ETF aren't held via anything
+                if fund.path.len() > 1 {
+                    let via = fund.path.last().unwrap();
+                    if !entry.via.contains(via) {
+                        entry.via.push(via.clone());
+                    }
+                }
+            }
+        }
+
+        let mut holdings: Vec<LookThroughHolding> = holdings.into_values().collect();
+        holdings.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+        Ok(LookThrough {
+            ticker: ticker.clone(),
+            holdings,
+            unexpanded,
+        })
+    }
+
+    /// Returns the ticker of a holding if it's an ETF we can fetch details for. Supported ETFs are
+    /// all listed in the US.
+    async fn supported_fund(&self, holding: &Holding) -> Option<String> {
+        let ticker = security_identifier(holding)?;
+        if !self
+            .ticker_mapping
+            .read()
+            .await
+            .is_us_exchange(&holding.exchange)
+        {
+            return None;
+        }
+        if self.etf_to_manager.read().await.contains_key(ticker) {
+            Some(ticker.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::mock::{self, MockManager};
+    use crate::symbology::vendor_symbols;
+    use crate::ticker::TickerMapping;
+    use crate::types::{LookThrough, UnexpandedReason};
+
+    async fn look_through(manager: MockManager, max_depth: usize) -> LookThrough {
+        let etf_holdings = mock::etf_holdings(vec![Arc::new(manager)]).await;
+        etf_holdings
+            .look_through(&"TOP".to_string(), max_depth)
+            .await
+            .unwrap()
+    }
+
+    fn weight(look_through: &LookThrough, ticker: &str) -> f64 {
+        look_through
+            .holdings
+            .iter()
+            .find(|holding| holding.ticker == ticker)
+            .map(|holding| holding.weight)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn multiplies_weights_through() {
+        let manager = MockManager::new(vec![
+            mock::equity_etf("TOP", &[("A", 50.0), ("MID", 50.0)]),
+            mock::equity_etf("MID", &[("A", 20.0), ("B", 80.0)]),
+        ]);
+        let look_through = look_through(manager, 3).await;
+        assert_eq!(weight(&look_through, "A"), 60.0);
+        assert_eq!(weight(&look_through, "B"), 40.0);
+        assert_eq!(look_through.holdings.len(), 2);
+        assert!(look_through.unexpanded.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failing_nested_fetch_is_kept_opaque() {
+        let manager =
+            MockManager::new(vec![mock::equity_etf("TOP", &[("A", 50.0), ("MID", 50.0)])])
+                .failing("MID");
+        let look_through = look_through(manager, 3).await;
+        assert_eq!(weight(&look_through, "MID"), 50.0);
+        assert_eq!(look_through.unexpanded.len(), 1);
+        assert_eq!(look_through.unexpanded[0].ticker, "MID");
+        assert_eq!(look_through.unexpanded[0].path, vec!["TOP".to_string()]);
+        assert!(matches!(
+            &look_through.unexpanded[0].reason,
+            UnexpandedReason::FetchFailed { error } if error.contains("Mock fetch of MID failed")
+        ));
+    }
+
+    #[tokio::test]
+    async fn cycles_and_max_depth_are_reported() {
+        let manager = MockManager::new(vec![
+            mock::equity_etf("TOP", &[("MID", 100.0)]),
+            mock::equity_etf("MID", &[("TOP", 50.0), ("LOW", 50.0)]),
+            mock::equity_etf("LOW", &[("A", 100.0)]),
+        ]);
+        let look_through = look_through(manager, 1).await;
+        let reasons: Vec<(&str, &UnexpandedReason)> = look_through
+            .unexpanded
+            .iter()
+            .map(|fund| (fund.ticker.as_str(), &fund.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                ("TOP", &UnexpandedReason::Cycle),
+                ("LOW", &UnexpandedReason::MaxDepth)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn same_ticker_on_another_exchange_is_not_the_fund() {
+        let mut foreign_mid = mock::holding("MID", "Equity", 50.0);
+        foreign_mid.exchange = "Asx - All Markets".to_string();
+        foreign_mid.symbols =
+            vendor_symbols(&TickerMapping::default(), "MID", &foreign_mid.exchange);
+        foreign_mid.ticker = "MID.AX".to_string();
+        let mut top = mock::equity_etf("TOP", &[("MID", 50.0)]);
+        top.holdings.push(foreign_mid);
+        let manager = MockManager::new(vec![top, mock::equity_etf("MID", &[("A", 100.0)])]);
+
+        let look_through = look_through(manager, 3).await;
+        assert_eq!(weight(&look_through, "A"), 50.0);
+        assert_eq!(weight(&look_through, "MID.AX"), 50.0);
+        assert_eq!(look_through.holdings.len(), 2);
+        assert!(look_through.unexpanded.is_empty());
+    }
+
+    #[tokio::test]
+    async fn bare_tickers_on_different_exchanges_are_kept_apart() {
+        let mut foreign_a = mock::holding("A", "Equity", 50.0);
+        foreign_a.exchange = "Mystery Exchange".to_string();
+        foreign_a.symbols = vendor_symbols(&TickerMapping::default(), "A", &foreign_a.exchange);
+        let mut top = mock::equity_etf("TOP", &[("MID", 50.0)]);
+        top.holdings.push(foreign_a);
+        let manager = MockManager::new(vec![top, mock::equity_etf("MID", &[("A", 100.0)])]);
+
+        let look_through = look_through(manager, 3).await;
+        assert_eq!(look_through.holdings.len(), 2);
+        assert!(look_through
+            .holdings
+            .iter()
+            .all(|holding| holding.ticker == "A" && holding.weight == 50.0));
+        let nasdaq_a = look_through
+            .holdings
+            .iter()
+            .find(|holding| holding.identifier.is_some())
+            .unwrap();
+        assert_eq!(nasdaq_a.via, vec!["MID"]);
+    }
+
+    #[test]
+    fn reasons_serialize_with_a_kind() {
+        let reason = UnexpandedReason::FetchFailed {
+            error: "down".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&reason).unwrap(),
+            r#"{"kind":"FetchFailed","error":"down"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&UnexpandedReason::MaxDepth).unwrap(),
+            r#"{"kind":"MaxDepth"}"#
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Concentration and diversification metrics of an ETF's holdings
+
+use std::collections::BTreeMap;
+
+use crate::types::{ConcentrationMetrics, ETFMetrics, Holding, ETF};
+
+/// Sizes of the top-N groups reported in `ConcentrationMetrics::top_weights`
+const TOP_N: [usize; 4] = [1, 5, 10, 25];
+
+/// Calculate concentration metrics for some holdings.
+///
+/// Weights are normalised to add up to 100% within the holdings given, so the equities only metrics
+/// aren't skewed by the fund's cash. Holdings with a zero or negative weight (e.g. short positions)
+/// are left out.
+pub fn concentration<'a>(holdings: impl Iterator<Item = &'a Holding>) -> ConcentrationMetrics {
+    let mut weights: Vec<f64> = holdings.map(|h| h.weight).filter(|w| *w > 0.0).collect();
+    let total: f64 = weights.iter().sum();
+    if weights.is_empty() || total <= 0.0 {
+        return ConcentrationMetrics::default();
+    }
+    for weight in weights.iter_mut() {
+        *weight /= total;
+    }
+    // Largest first
+    weights.sort_by(|a, b| b.total_cmp(a));
+
+    let top_weights: BTreeMap<usize, f64> = TOP_N
+        .iter()
+        .map(|n| (*n, weights.iter().take(*n).sum::<f64>() * 100.0))
+        .collect();
+
+    let hhi: f64 = weights.iter().map(|w| w * w).sum();
+
+    // Gini over the weights in ascending order: 0 is equal weighted, close to 1 is a single holding
+    let count = weights.len() as f64;
+    let weighted_rank_sum: f64 = weights
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, w)| (i + 1) as f64 * w)
+        .sum();
+    let gini = 2.0 * weighted_rank_sum / count - (count + 1.0) / count;
+
+    ConcentrationMetrics {
+        holdings_count: weights.len(),
+        top_weights,
+        hhi,
+        effective_holdings: 1.0 / hhi,
+        gini,
+    }
+}
+
+/// Calculate concentration metrics of an ETF for all holdings and equities only.
+pub fn etf_metrics(etf: &ETF) -> ETFMetrics {
+    ETFMetrics {
+        all: concentration(etf.holdings.iter()),
+        equities: concentration(etf.holdings.iter().filter(|h| h.asset_class == "Equity")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    fn metrics(weights: &[f64]) -> ConcentrationMetrics {
+        let holdings: Vec<Holding> = weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| mock::holding(&format!("H{}", i), "Equity", *weight))
+            .collect();
+        concentration(holdings.iter())
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn equal_weights_are_not_concentrated() {
+        for n in [2, 7, 40] {
+            let metrics = metrics(&vec![100.0 / n as f64; n]);
+            assert_eq!(metrics.holdings_count, n);
+            assert_close(metrics.gini, 0.0);
+            assert_close(metrics.hhi, 1.0 / n as f64);
+            assert_close(metrics.effective_holdings, n as f64);
+        }
+    }
+
+    #[test]
+    fn a_single_holding_is_fully_concentrated() {
+        let metrics = metrics(&[100.0]);
+        assert_close(metrics.hhi, 1.0);
+        assert_close(metrics.effective_holdings, 1.0);
+        assert_close(metrics.gini, 0.0);
+        for n in TOP_N {
+            assert_close(metrics.top_weights[&n], 100.0);
+        }
+    }
+
+    #[test]
+    fn known_weights() {
+        let metrics = metrics(&[10.0, 40.0, 20.0, 30.0]);
+        assert_close(metrics.hhi, 0.3);
+        assert_close(metrics.effective_holdings, 1.0 / 0.3);
+        // Ascending 0.1, 0.2, 0.3, 0.4: 2 * (0.1 + 0.4 + 0.9 + 1.6) / 4 - 5 / 4
+        assert_close(metrics.gini, 0.25);
+    }
+
+    #[test]
+    fn top_weights_add_up_the_largest_holdings() {
+        // 30 holdings weighing 1 to 30, normalised by their total of 465
+        let weights: Vec<f64> = (1..=30).map(f64::from).collect();
+        let metrics = metrics(&weights);
+        let top = |n: usize| (31 - n..=30).sum::<usize>() as f64 / 465.0 * 100.0;
+        assert_eq!(metrics.top_weights.len(), TOP_N.len());
+        for n in TOP_N {
+            assert_close(metrics.top_weights[&n], top(n));
+        }
+    }
+
+    #[test]
+    fn normalises_weights_and_skips_short_positions() {
+        let metrics = metrics(&[25.0, 25.0, -10.0, 0.0]);
+        assert_eq!(metrics.holdings_count, 2);
+        assert_close(metrics.hhi, 0.5);
+        assert_close(metrics.top_weights[&1], 50.0);
+    }
+
+    #[test]
+    fn no_holdings_have_no_metrics() {
+        let metrics = metrics(&[]);
+        assert_eq!(metrics.holdings_count, 0);
+        assert!(metrics.top_weights.is_empty());
+    }
+
+    #[test]
+    fn equities_only_metrics_leave_out_other_holdings() {
+        let etf = mock::etf(
+            "AAA",
+            vec![
+                mock::holding("X", "Equity", 45.0),
+                mock::holding("Y", "Equity", 45.0),
+                mock::holding("USD", "Cash", 10.0),
+            ],
+        );
+        let metrics = etf_metrics(&etf);
+        assert_eq!(metrics.all.holdings_count, 3);
+        assert_eq!(metrics.equities.holdings_count, 2);
+        assert_close(metrics.equities.effective_holdings, 2.0);
+    }
+}
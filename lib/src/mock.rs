@@ -0,0 +1,213 @@
+//! Fund manager and ETFs for tests, no network involved
+
+use async_trait::async_trait;
+use futures::FutureExt;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::symbology::vendor_symbols;
+use crate::ticker::TickerMapping;
+use crate::types::{
+    ETFListItem, Error, FundManager, Holding, ManagerContext, RetryPolicy, ValidationReport, ETF,
+    SCHEMA_VERSION,
+};
+use crate::upstream::UpstreamClient;
+use crate::{ETFHoldings, ManagerConstructor, DEFAULT_SNAPSHOT_TTL};
+
+/// A holding on NASDAQ, or without an exchange unless it's an equity
+pub(crate) fn holding(ticker: &str, asset_class: &str, weight: f64) -> Holding {
+    let exchange = if asset_class == "Equity" {
+        "NASDAQ"
+    } else {
+        "-"
+    };
+    Holding {
+        ticker: ticker.to_string(),
+        name: format!("{} Name", ticker),
+        asset_class: asset_class.to_string(),
+        sector: None,
+        market_value: weight * 1000.0,
+        weight,
+        notional_value: Some(weight * 1000.0),
+        shares: Some(weight),
+        price: Some(1000.0),
+        location: "United States".to_string(),
+        exchange: exchange.to_string(),
+        currency: "USD".to_string(),
+        fx_rate: Some(1.0),
+        market_currency: "USD".to_string(),
+        symbols: vendor_symbols(&TickerMapping::default(), ticker, exchange),
+    }
+}
+
+/// An ETF with the holdings, worth 100,000 USD in 100 shares
+pub(crate) fn etf(ticker: &str, holdings: Vec<Holding>) -> ETF {
+    ETF {
+        schema_version: SCHEMA_VERSION,
+        ticker: ticker.to_string(),
+        name: format!("{} ETF", ticker),
+        currency: "USD".to_string(),
+        last_update: "Oct 15, 2021".to_string(),
+        outstanding_shares: 100.0,
+        holdings,
+        unresolved_holdings: Vec::new(),
+        warnings: Vec::new(),
+        validation: ValidationReport::default(),
+    }
+}
+
+/// An ETF with equity holdings of the tickers and weights (%)
+pub(crate) fn equity_etf(ticker: &str, holdings: &[(&str, f64)]) -> ETF {
+    let holdings = holdings
+        .iter()
+        .map(|(ticker, weight)| holding(ticker, "Equity", *weight))
+        .collect();
+    etf(ticker, holdings)
+}
+
+/// Fund manager serving ETFs from memory and counting fetches
+#[derive(Default)]
+pub(crate) struct MockManager {
+    etfs: Mutex<HashMap<String, ETF>>,
+    /// Tickers that are listed but fail to fetch
+    failing: HashSet<String>,
+    delay: Duration,
+    /// Delays of tickers that don't take `delay`
+    ticker_delays: HashMap<String, Duration>,
+    calls: Mutex<HashMap<String, usize>>,
+    /// Fetches running right now, and the most that ever ran at the same time
+    running: AtomicUsize,
+    max_running: AtomicUsize,
+}
+
+impl MockManager {
+    pub(crate) fn new(etfs: Vec<ETF>) -> MockManager {
+        MockManager {
+            etfs: Mutex::new(
+                etfs.into_iter()
+                    .map(|etf| (etf.ticker.clone(), etf))
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    /// Also list `ticker` but fail to fetch it.
+    pub(crate) fn failing(mut self, ticker: &str) -> MockManager {
+        self.failing.insert(ticker.to_string());
+        self
+    }
+
+    /// Take `delay` for every fetch.
+    pub(crate) fn with_delay(mut self, delay: Duration) -> MockManager {
+        self.delay = delay;
+        self
+    }
+
+    /// Serve a new snapshot of an ETF from now on.
+    pub(crate) fn publish(&self, etf: ETF) {
+        self.etfs.lock().unwrap().insert(etf.ticker.clone(), etf);
+    }
+
+    /// Take `delay` for fetches of `ticker`.
+    pub(crate) fn with_ticker_delay(mut self, ticker: &str, delay: Duration) -> MockManager {
+        self.ticker_delays.insert(ticker.to_string(), delay);
+        self
+    }
+
+    /// The most fetches that ran at the same time.
+    pub(crate) fn max_concurrent_calls(&self) -> usize {
+        self.max_running.load(Ordering::SeqCst)
+    }
+
+    /// How many times `ticker` was fetched.
+    pub(crate) fn calls(&self, ticker: &str) -> usize {
+        self.calls
+            .lock()
+            .unwrap()
+            .get(ticker)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl FundManager for MockManager {
+    async fn new(_: ManagerContext) -> Result<Self, Error> {
+        Err(Error::from("MockManager is created with MockManager::new"))
+    }
+
+    fn etfs_under_management(&self) -> Vec<ETFListItem> {
+        let etfs = self.etfs.lock().unwrap();
+        let mut tickers: Vec<&String> = etfs.keys().chain(&self.failing).collect();
+        tickers.sort();
+        tickers
+            .into_iter()
+            .map(|ticker| ETFListItem {
+                ticker: ticker.clone(),
+                name: format!("{} ETF", ticker),
+            })
+            .collect()
+    }
+
+    fn host(&self) -> &str {
+        "mock.example"
+    }
+
+    async fn etf_details(&self, ticker: &str) -> Result<ETF, Error> {
+        *self
+            .calls
+            .lock()
+            .unwrap()
+            .entry(ticker.to_string())
+            .or_default() += 1;
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(running, Ordering::SeqCst);
+        let delay = self.ticker_delays.get(ticker).unwrap_or(&self.delay);
+        tokio::time::sleep(*delay).await;
+        self.running.fetch_sub(1, Ordering::SeqCst);
+        if self.failing.contains(ticker) {
+            return Err(Error::from(format!("Mock fetch of {} failed", ticker)));
+        }
+        let etf = self.etfs.lock().unwrap().get(ticker).cloned();
+        etf.ok_or(Error::NotFound)
+    }
+}
+
+/// Context for fund managers, without a ticker mapping file
+pub(crate) fn context() -> ManagerContext {
+    let upstream =
+        UpstreamClient::new(Duration::from_secs(1), RetryPolicy::default(), None, None).unwrap();
+    ManagerContext {
+        ticker_mapping: Arc::new(RwLock::new(TickerMapping::default())),
+        upstream: Arc::new(upstream),
+        lenient_parsing: false,
+    }
+}
+
+/// `ETFHoldings` with mock fund managers instead of the real ones
+pub(crate) async fn etf_holdings(managers: Vec<Arc<MockManager>>) -> ETFHoldings {
+    etf_holdings_with_ttl(managers, DEFAULT_SNAPSHOT_TTL).await
+}
+
+/// `ETFHoldings` with mock fund managers, fetching ETFs again after `snapshot_ttl`
+pub(crate) async fn etf_holdings_with_ttl(
+    managers: Vec<Arc<MockManager>>,
+    snapshot_ttl: Duration,
+) -> ETFHoldings {
+    let constructors = managers
+        .into_iter()
+        .enumerate()
+        .map(|(i, manager)| {
+            let constructor: ManagerConstructor = Arc::new(move |_| {
+                let manager: Arc<dyn FundManager> = manager.clone();
+                async move { Ok(manager) }.boxed()
+            });
+            (format!("Mock {}", i), constructor)
+        })
+        .collect();
+    ETFHoldings::with_constructors(context(), None, snapshot_ttl, constructors).await
+}
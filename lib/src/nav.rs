@@ -0,0 +1,65 @@
+//! Net asset value of an ETF worked out from its holdings
+
+use crate::types::ETF;
+
+/// Net assets of an ETF, the market value of all holdings (including cash and derivatives).
+pub fn net_assets(etf: &ETF) -> f64 {
+    etf.holdings.iter().map(|h| h.market_value).sum()
+}
+
+/// Implied NAV per share, the market value of all holdings (including cash and derivatives)
+/// divided by outstanding shares. Returns `None` if the ETF has no outstanding shares.
+pub fn implied_nav(etf: &ETF) -> Option<f64> {
+    if etf.outstanding_shares <= 0.0 {
+        return None;
+    }
+    Some(net_assets(etf) / etf.outstanding_shares)
+}
+
+/// Premium (positive) or discount (negative) of the market price to the NAV in %.
+pub fn premium_discount(nav: f64, market_price: f64) -> f64 {
+    (market_price / nav - 1.0) * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    /// 95,000 USD of equities and 5,000 USD cash in 100 shares
+    fn etf() -> ETF {
+        mock::etf(
+            "AAA",
+            vec![
+                mock::holding("X", "Equity", 95.0),
+                mock::holding("USD", "Cash", 5.0),
+            ],
+        )
+    }
+
+    #[test]
+    fn net_assets_include_cash() {
+        assert_eq!(net_assets(&etf()), 100_000.0);
+    }
+
+    #[test]
+    fn implied_nav_is_net_assets_per_share() {
+        assert_eq!(implied_nav(&etf()), Some(1000.0));
+    }
+
+    #[test]
+    fn no_implied_nav_without_outstanding_shares() {
+        let mut etf = etf();
+        for outstanding_shares in [0.0, -100.0] {
+            etf.outstanding_shares = outstanding_shares;
+            assert_eq!(implied_nav(&etf), None);
+        }
+    }
+
+    #[test]
+    fn premium_and_discount_to_nav() {
+        assert_eq!(premium_discount(100.0, 102.0).round(), 2.0);
+        assert_eq!(premium_discount(100.0, 97.0).round(), -3.0);
+        assert_eq!(premium_discount(100.0, 100.0), 0.0);
+    }
+}
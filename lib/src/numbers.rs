@@ -0,0 +1,290 @@
+//! Parse numbers the way fund managers format them in their holdings files
+//!
+//! Every source has its own quirks, so each one picks a `NumberFormat` profile. On top of the
+//! separators, all profiles handle:
+//! * placeholders for missing values (`-`, `--`, `N/A`, blank cells)
+//! * accounting style negatives, e.g. `(1,234.56)`
+//! * trailing percent signs, e.g. `12.5%` is parsed as `12.5`
+//!
+//! Thousands separators have to group digits in threes, `1,23` is an error rather than `123`.
+//! Exponents aren't accepted, no fund manager writes them and `1e5` is more likely a broken cell.
+
+use std::fmt;
+
+/// Strings used for missing values
+const PLACEHOLDERS: [&str; 5] = ["", "-", "--", "N/A", "NA"];
+
+/// Format profile for the thousands and decimal separators
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NumberFormat {
+    /// `1,234.56` (US, UK, Australian sites)
+    English,
+    /// `1.234,56` (German, Spanish, Italian sites)
+    European,
+    /// `1'234.56` or `1’234.56` (Swiss sites)
+    Swiss,
+}
+
+/// Error for strings that aren't a number in the expected format
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseNumberError {
+    pub input: String,
+    pub format: NumberFormat,
+}
+
+impl fmt::Display for ParseNumberError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "can't parse \"{}\" as a {:?} number",
+            self.input, self.format
+        )
+    }
+}
+
+impl NumberFormat {
+    /// Returns the thousands and decimal separators
+    fn separators(&self) -> (char, char) {
+        match self {
+            NumberFormat::English => (',', '.'),
+            NumberFormat::European => ('.', ','),
+            NumberFormat::Swiss => ('\'', '.'),
+        }
+    }
+
+    /// Whether a character separates thousands, spaces are accepted by all profiles
+    fn is_thousands_separator(&self, c: char) -> bool {
+        let (thousands, _) = self.separators();
+        c == thousands || c == ' ' || (*self == NumberFormat::Swiss && c == '’')
+    }
+
+    /// Parse a number that might be missing, placeholders are returned as `None`.
+    ///
+    /// Examples:
+    ///
+    /// ```
+    /// use etf_holdings_lib::numbers::NumberFormat;
+    ///
+    /// assert_eq!(NumberFormat::English.parse("123,342.28"), Ok(Some(123342.28)));
+    /// assert_eq!(NumberFormat::European.parse("1.234,56"), Ok(Some(1234.56)));
+    /// assert_eq!(NumberFormat::English.parse("(12.50)"), Ok(Some(-12.5)));
+    /// assert_eq!(NumberFormat::English.parse("4.2%"), Ok(Some(4.2)));
+    /// assert_eq!(NumberFormat::English.parse("-"), Ok(None));
+    /// ```
+    pub fn parse(&self, input: &str) -> Result<Option<f64>, ParseNumberError> {
+        let error = || ParseNumberError {
+            input: input.to_string(),
+            format: *self,
+        };
+
+        let mut number = input.trim();
+        if PLACEHOLDERS
+            .iter()
+            .any(|placeholder| number.eq_ignore_ascii_case(placeholder))
+        {
+            return Ok(None);
+        }
+
+        // The percent sign can be inside or outside the parentheses, e.g. (1.5%) or (1.5)%
+        number = number.strip_suffix('%').unwrap_or(number).trim_end();
+        let mut negative = false;
+        if number.starts_with('(') && number.ends_with(')') {
+            negative = true;
+            number = number[1..number.len() - 1].trim();
+            number = number.strip_suffix('%').unwrap_or(number).trim_end();
+        }
+
+        let (sign, unsigned) = match number.chars().next() {
+            Some(sign @ ('-' | '+')) => (Some(sign), number[1..].trim_start()),
+            _ => (None, number),
+        };
+        if negative && sign.is_some() {
+            return Err(error());
+        }
+        let negative = negative || sign == Some('-');
+
+        let (_, decimal) = self.separators();
+        let (integer, fraction) = match unsigned.split_once(decimal) {
+            Some((integer, fraction)) => (integer, fraction),
+            None => (unsigned, ""),
+        };
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if (integer.is_empty() && fraction.is_empty()) || !is_digits(fraction) {
+            return Err(error());
+        }
+
+        // Without separators any number of digits is fine, with them every group after the first
+        // one has exactly three digits
+        let mut groups = integer.split(|c| self.is_thousands_separator(c));
+        let first = groups.next().unwrap_or_default();
+        let grouped = first.len() < integer.len();
+        let valid_grouping = is_digits(first)
+            && (!grouped || (1..=3).contains(&first.len()))
+            && groups.all(|group| group.len() == 3 && is_digits(group));
+        if !valid_grouping {
+            return Err(error());
+        }
+
+        let digits: String = integer.chars().filter(char::is_ascii_digit).collect();
+        let value: f64 = format!("{}.{}", digits, fraction)
+            .parse()
+            .map_err(|_| error())?;
+        if negative {
+            Ok(Some(-value))
+        } else {
+            Ok(Some(value))
+        }
+    }
+
+    /// Parse a number that has to be there, placeholders are an error.
+    ///
+    /// ```
+    /// use etf_holdings_lib::numbers::NumberFormat;
+    ///
+    /// assert_eq!(NumberFormat::Swiss.parse_required("1'234.56"), Ok(1234.56));
+    /// assert!(NumberFormat::Swiss.parse_required("N/A").is_err());
+    /// ```
+    pub fn parse_required(&self, input: &str) -> Result<f64, ParseNumberError> {
+        self.parse(input)?.ok_or_else(|| ParseNumberError {
+            input: input.to_string(),
+            format: *self,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const FORMATS: [NumberFormat; 3] = [
+        NumberFormat::English,
+        NumberFormat::European,
+        NumberFormat::Swiss,
+    ];
+
+    /// Format a number the way a holdings file in `format` would
+    fn format_number(format: NumberFormat, integer: u64, fraction: &str, grouped: bool) -> String {
+        let (thousands, decimal) = format.separators();
+        let digits = integer.to_string();
+        let mut formatted = String::new();
+        for (i, digit) in digits.chars().enumerate() {
+            if grouped && i > 0 && (digits.len() - i).is_multiple_of(3) {
+                formatted.push(thousands);
+            }
+            formatted.push(digit);
+        }
+        if !fraction.is_empty() {
+            formatted.push(decimal);
+            formatted.push_str(fraction);
+        }
+        formatted
+    }
+
+    fn expected(integer: u64, fraction: &str) -> f64 {
+        format!("{}.{}", integer, fraction).parse().unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn round_trips(
+            format in prop::sample::select(FORMATS.to_vec()),
+            integer in 0u64..1_000_000_000_000,
+            fraction in "[0-9]{0,4}",
+            grouped: bool,
+        ) {
+            let formatted = format_number(format, integer, &fraction, grouped);
+            prop_assert_eq!(format.parse(&formatted), Ok(Some(expected(integer, &fraction))));
+        }
+
+        #[test]
+        fn round_trips_negatives_and_percent(
+            format in prop::sample::select(FORMATS.to_vec()),
+            integer in 0u64..1_000_000_000,
+            fraction in "[0-9]{0,4}",
+            parentheses: bool,
+            percent: bool,
+        ) {
+            let formatted = format_number(format, integer, &fraction, true);
+            let formatted = match (parentheses, percent) {
+                (true, true) => format!("({}%)", formatted),
+                (true, false) => format!("({})", formatted),
+                (false, true) => format!("-{}%", formatted),
+                (false, false) => format!("-{}", formatted),
+            };
+            prop_assert_eq!(format.parse(&formatted), Ok(Some(-expected(integer, &fraction))));
+        }
+    }
+
+    #[test]
+    fn placeholders_and_blank_cells_are_missing() {
+        for format in FORMATS {
+            for placeholder in ["", "  ", "-", "--", "N/A", "n/a", "NA"] {
+                assert_eq!(format.parse(placeholder), Ok(None), "{:?}", placeholder);
+                assert!(format.parse_required(placeholder).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn parentheses_are_negative() {
+        assert_eq!(
+            NumberFormat::English.parse("(1,234.56)"),
+            Ok(Some(-1234.56))
+        );
+        assert_eq!(
+            NumberFormat::European.parse("( 1.234,56 )"),
+            Ok(Some(-1234.56))
+        );
+        assert_eq!(NumberFormat::English.parse("(1.5%)"), Ok(Some(-1.5)));
+        assert_eq!(NumberFormat::English.parse("(1.5)%"), Ok(Some(-1.5)));
+        assert!(NumberFormat::English.parse("(-1.5)").is_err());
+        assert!(NumberFormat::English.parse("(1.5").is_err());
+    }
+
+    #[test]
+    fn percent_sign_is_dropped() {
+        assert_eq!(NumberFormat::English.parse("12.5%"), Ok(Some(12.5)));
+        assert_eq!(NumberFormat::European.parse("12,5 %"), Ok(Some(12.5)));
+        assert!(NumberFormat::English.parse("%").is_err());
+    }
+
+    #[test]
+    fn exponents_are_rejected() {
+        for input in ["1e5", "1E5", "1.5e-3", "2E+2"] {
+            assert!(NumberFormat::English.parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn bad_grouping_is_rejected() {
+        for input in [
+            "1,23", "12,3456", "1,,234", ",123", "1234,567", "1,234,56", "1.2,3",
+        ] {
+            assert!(NumberFormat::English.parse(input).is_err(), "{}", input);
+        }
+        assert!(NumberFormat::European.parse("1.23").is_err());
+        assert!(NumberFormat::Swiss.parse("1'23.5").is_err());
+    }
+
+    #[test]
+    fn ungrouped_numbers_are_fine() {
+        assert_eq!(
+            NumberFormat::English.parse("1234567.5"),
+            Ok(Some(1234567.5))
+        );
+        assert_eq!(NumberFormat::English.parse(".5"), Ok(Some(0.5)));
+        assert_eq!(NumberFormat::English.parse("+5"), Ok(Some(5.0)));
+        assert_eq!(NumberFormat::English.parse("1 234.5"), Ok(Some(1234.5)));
+    }
+
+    #[test]
+    fn swiss_accepts_typographic_apostrophe() {
+        assert_eq!(NumberFormat::Swiss.parse("1'234.56"), Ok(Some(1234.56)));
+        assert_eq!(
+            NumberFormat::Swiss.parse("1’234’567.5"),
+            Ok(Some(1234567.5))
+        );
+        assert!(NumberFormat::English.parse("1’234.56").is_err());
+    }
+}
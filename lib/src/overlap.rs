@@ -0,0 +1,147 @@
+//! Compare the holdings of multiple ETFs
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::symbology::security_identifier;
+use crate::types::{CommonHolding, Overlap, ETF};
+
+/// Weight (%) of each security in an ETF by its identifier, securities listed more than once are
+/// added up
+fn security_weights(etf: &ETF) -> HashMap<&str, f64> {
+    let mut weights = HashMap::new();
+    for holding in &etf.holdings {
+        if let Some(identifier) = security_identifier(holding) {
+            *weights.entry(identifier).or_insert(0.0) += holding.weight;
+        }
+    }
+    weights
+}
+
+/// Sum of the smaller weight of every holding two ETFs have in common
+fn pairwise_overlap(a: &HashMap<&str, f64>, b: &HashMap<&str, f64>) -> f64 {
+    a.iter()
+        .filter_map(|(ticker, weight_a)| Some(weight_a.min(*b.get(ticker)?)))
+        .sum()
+}
+
+/// Work out how much the holdings of a few ETFs overlap.
+///
+/// Only securities are matched, by their identifier (see `security_identifier`), so cash in the
+/// same currency or holdings on unmapped exchanges don't count as overlap. The weighted overlap is
+/// the sum of the smallest weight of each security that all the ETFs have in common, i.e. the
+/// percentage of every fund that's invested in the same things.
+///
+/// An ETF that's passed more than once is only compared once.
+pub fn overlap(etfs: &[ETF]) -> Overlap {
+    let mut seen_tickers = HashSet::new();
+    let etfs: Vec<&ETF> = etfs
+        .iter()
+        .filter(|etf| seen_tickers.insert(etf.ticker.as_str()))
+        .collect();
+    let weights: Vec<HashMap<&str, f64>> = etfs.iter().map(|etf| security_weights(etf)).collect();
+
+    let mut common_holdings = Vec::new();
+    if let Some(first) = etfs.first() {
+        // Securities are listed once in the order of the first ETF
+        let mut seen = BTreeSet::new();
+        for holding in &first.holdings {
+            let identifier = match security_identifier(holding) {
+                Some(identifier) => identifier,
+                None => continue,
+            };
+            if !seen.insert(identifier) {
+                continue;
+            }
+            let holding_weights: Option<Vec<f64>> =
+                weights.iter().map(|w| w.get(identifier).copied()).collect();
+            if let Some(holding_weights) = holding_weights {
+                common_holdings.push(CommonHolding {
+                    ticker: identifier.to_string(),
+                    name: holding.name.clone(),
+                    weights: holding_weights,
+                });
+            }
+        }
+    }
+    let weighted_overlap = common_holdings
+        .iter()
+        .map(|h| h.weights.iter().cloned().fold(f64::INFINITY, f64::min))
+        .sum();
+
+    let mut unique_holdings = HashMap::new();
+    for (i, etf) in etfs.iter().enumerate() {
+        let mut unique: Vec<String> = weights[i]
+            .keys()
+            .filter(|ticker| {
+                weights
+                    .iter()
+                    .enumerate()
+                    .all(|(j, other)| i == j || !other.contains_key(*ticker))
+            })
+            .map(|ticker| ticker.to_string())
+            .collect();
+        unique.sort();
+        unique_holdings.insert(etf.ticker.clone(), unique);
+    }
+
+    let matrix = weights
+        .iter()
+        .map(|a| weights.iter().map(|b| pairwise_overlap(a, b)).collect())
+        .collect();
+
+    Overlap {
+        tickers: etfs.iter().map(|etf| etf.ticker.clone()).collect(),
+        common_holdings,
+        weighted_overlap,
+        unique_holdings,
+        matrix,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+    use crate::symbology::vendor_symbols;
+    use crate::ticker::TickerMapping;
+
+    #[test]
+    fn matches_securities_only() {
+        let mut a = mock::equity_etf("A", &[("AAPL", 40.0), ("MSFT", 40.0)]);
+        a.holdings.push(mock::holding("USD", "Cash", 20.0));
+        let mut b = mock::equity_etf("B", &[("AAPL", 30.0), ("GOOG", 50.0)]);
+        b.holdings.push(mock::holding("USD", "Cash", 20.0));
+
+        let overlap = overlap(&[a, b]);
+        assert_eq!(overlap.common_holdings.len(), 1);
+        assert_eq!(overlap.common_holdings[0].ticker, "AAPL");
+        assert_eq!(overlap.common_holdings[0].weights, vec![40.0, 30.0]);
+        assert_eq!(overlap.weighted_overlap, 30.0);
+        assert_eq!(overlap.unique_holdings["A"], vec!["MSFT".to_string()]);
+        assert_eq!(overlap.unique_holdings["B"], vec!["GOOG".to_string()]);
+        assert_eq!(overlap.matrix, vec![vec![80.0, 30.0], vec![30.0, 80.0]]);
+    }
+
+    #[test]
+    fn same_ticker_on_different_exchanges_is_different() {
+        let a = mock::equity_etf("A", &[("BHP", 100.0)]);
+        let mut b = mock::equity_etf("B", &[("BHP", 100.0)]);
+        b.holdings[0].symbols =
+            vendor_symbols(&TickerMapping::default(), "BHP", "Asx - All Markets");
+
+        let overlap = overlap(&[a, b]);
+        assert!(overlap.common_holdings.is_empty());
+        assert_eq!(overlap.weighted_overlap, 0.0);
+    }
+
+    #[test]
+    fn repeated_etfs_are_compared_once() {
+        let a = mock::equity_etf("A", &[("AAPL", 100.0)]);
+        let b = mock::equity_etf("B", &[("AAPL", 50.0), ("MSFT", 50.0)]);
+
+        let overlap = overlap(&[a.clone(), b, a]);
+        assert_eq!(overlap.tickers, vec!["A".to_string(), "B".to_string()]);
+        assert_eq!(overlap.common_holdings[0].weights, vec![100.0, 50.0]);
+        assert_eq!(overlap.matrix.len(), 2);
+    }
+}
@@ -0,0 +1,233 @@
+//! Combine a mix of ETFs into the underlying securities they're invested in
+
+use std::collections::HashMap;
+
+use crate::look_through::HoldingKey;
+use crate::nav::implied_nav;
+use crate::types::{Error, Portfolio, PortfolioHolding, PortfolioLookThrough, PositionSize, ETF};
+use crate::ETFHoldings;
+
+/// How many levels of funds holding other funds to look through
+const LOOK_THROUGH_DEPTH: usize = 3;
+
+/// Price of one unit of an ETF worked out from its holdings
+fn nav_per_unit(etf: &ETF) -> Result<f64, Error> {
+    implied_nav(etf).ok_or_else(|| {
+        Error::from(format!(
+            "Can't value units of {}, it has no outstanding shares.",
+            etf.ticker
+        ))
+    })
+}
+
+impl Portfolio {
+    /// Check every position has a positive, finite size.
+    pub fn validate(&self) -> Result<(), String> {
+        for position in &self.positions {
+            let (kind, size) = match position.size {
+                PositionSize::Amount(amount) => ("amount", amount),
+                PositionSize::Units(units) => ("units", units),
+            };
+            if !(size.is_finite() && size > 0.0) {
+                return Err(format!(
+                    "Invalid {} {} for {}, it must be a positive number.",
+                    kind, size, position.ticker
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Work out the portfolio's exposure to each underlying security. Securities are matched
+    /// across ETFs by their identifier, like in `ETFHoldings::look_through`.
+    ///
+    /// Positions given in units are valued at the ETF's NAV per unit (net assets from the holdings
+    /// divided by outstanding shares), since that's the price the holdings data is based on. Funds
+    /// holding other supported ETFs are looked through as well (see `ETFHoldings::look_through`).
+    ///
+    /// Amounts are added up as they are, so all ETFs must be in the same currency.
+    pub async fn look_through(
+        &self,
+        etf_holdings: &ETFHoldings,
+    ) -> Result<PortfolioLookThrough, Error> {
+        self.validate()?;
+
+        let mut etfs = Vec::new();
+        for position in &self.positions {
+            etfs.push(etf_holdings.etf_details(&position.ticker).await?);
+        }
+        let currency = etfs.first().map(|etf| etf.currency.clone());
+        if let Some(other) = etfs
+            .iter()
+            .find(|etf| Some(&etf.currency) != currency.as_ref())
+        {
+            return Err(Error::from(format!(
+                "Can't combine {} in {} with {} in {}, all ETFs must be in the same currency.",
+                etfs[0].ticker, etfs[0].currency, other.ticker, other.currency
+            )));
+        }
+
+        let mut holdings: HashMap<HoldingKey, PortfolioHolding> = HashMap::new();
+        let mut total_amount = 0.0;
+
+        for (position, etf) in self.positions.iter().zip(&etfs) {
+            let amount = match position.size {
+                PositionSize::Amount(amount) => amount,
+                PositionSize::Units(units) => units * nav_per_unit(etf)?,
+            };
+            total_amount += amount;
+
+            let look_through = etf_holdings
+                .look_through(&position.ticker, LOOK_THROUGH_DEPTH)
+                .await?;
+            for holding in look_through.holdings {
+                let holding_amount = amount * holding.weight / 100.0;
+                let entry =
+                    holdings
+                        .entry(HoldingKey::of(&holding))
+                        .or_insert_with(|| PortfolioHolding {
+                            ticker: holding.ticker,
+                            identifier: holding.identifier,
+                            name: holding.name,
+                            asset_class: holding.asset_class,
+                            amount: 0.0,
+                            weight: 0.0,
+                            via: Vec::new(),
+                        });
+                entry.amount += holding_amount;
+                if !entry.via.contains(&position.ticker) {
+                    entry.via.push(position.ticker.clone());
+                }
+            }
+        }
+
+        let mut holdings: Vec<PortfolioHolding> = holdings.into_values().collect();
+        if total_amount != 0.0 {
+            for holding in holdings.iter_mut() {
+                holding.weight = holding.amount / total_amount * 100.0;
+            }
+        }
+        holdings.sort_by(|a, b| b.amount.total_cmp(&a.amount));
+        Ok(PortfolioLookThrough {
+            currency,
+            total_amount,
+            holdings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::mock::{self, MockManager};
+    use crate::symbology::vendor_symbols;
+    use crate::ticker::TickerMapping;
+    use crate::types::{Holding, PortfolioPosition};
+
+    fn portfolio(positions: &[(&str, PositionSize)]) -> Portfolio {
+        Portfolio {
+            positions: positions
+                .iter()
+                .map(|(ticker, size)| PortfolioPosition {
+                    ticker: ticker.to_string(),
+                    size: *size,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn rejects_sizes_that_are_not_positive() {
+        for size in [
+            PositionSize::Amount(-100.0),
+            PositionSize::Amount(0.0),
+            PositionSize::Amount(f64::NAN),
+            PositionSize::Units(-1.0),
+            PositionSize::Units(f64::INFINITY),
+        ] {
+            let error = portfolio(&[("AAA", size)]).validate().unwrap_err();
+            assert!(error.contains("AAA"), "{}", error);
+        }
+        assert!(portfolio(&[("AAA", PositionSize::Units(0.5))])
+            .validate()
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_mixed_currencies() {
+        let mut bbb = mock::equity_etf("BBB", &[("BHP", 100.0)]);
+        bbb.currency = "AUD".to_string();
+        let etf_holdings = mock::etf_holdings(vec![Arc::new(MockManager::new(vec![
+            mock::equity_etf("AAA", &[("AAPL", 100.0)]),
+            bbb,
+        ]))])
+        .await;
+
+        let error = portfolio(&[
+            ("AAA", PositionSize::Amount(100.0)),
+            ("BBB", PositionSize::Amount(100.0)),
+        ])
+        .look_through(&etf_holdings)
+        .await
+        .unwrap_err();
+        assert!(matches!(error, Error::Generic(msg) if msg.contains("same currency")));
+    }
+
+    #[tokio::test]
+    async fn adds_up_positions() {
+        let etf_holdings = mock::etf_holdings(vec![Arc::new(MockManager::new(vec![
+            mock::equity_etf("AAA", &[("AAPL", 60.0), ("MSFT", 40.0)]),
+            mock::equity_etf("BBB", &[("AAPL", 100.0)]),
+        ]))])
+        .await;
+
+        let look_through = portfolio(&[
+            ("AAA", PositionSize::Amount(100.0)),
+            ("BBB", PositionSize::Amount(100.0)),
+        ])
+        .look_through(&etf_holdings)
+        .await
+        .unwrap();
+        assert_eq!(look_through.currency.as_deref(), Some("USD"));
+        assert_eq!(look_through.total_amount, 200.0);
+        assert_eq!(look_through.holdings[0].amount, 160.0);
+        assert_eq!(look_through.holdings[0].via, vec!["AAA", "BBB"]);
+        assert_eq!(look_through.holdings[1].weight, 20.0);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_same_ticker_on_different_exchanges_apart() {
+        let mut bhp_au = mock::holding("BHP", "Equity", 50.0);
+        bhp_au.exchange = "Asx - All Markets".to_string();
+        bhp_au.symbols = vendor_symbols(&TickerMapping::default(), "BHP", &bhp_au.exchange);
+        bhp_au.ticker = "BHP.AX".to_string();
+        let mut aaa = mock::equity_etf("AAA", &[("BHP", 50.0)]);
+        aaa.holdings.push(bhp_au.clone());
+        let mut bbb = mock::equity_etf("BBB", &[]);
+        bbb.holdings.push(Holding {
+            weight: 100.0,
+            ..bhp_au
+        });
+        let etf_holdings =
+            mock::etf_holdings(vec![Arc::new(MockManager::new(vec![aaa, bbb]))]).await;
+
+        let look_through = portfolio(&[
+            ("AAA", PositionSize::Amount(100.0)),
+            ("BBB", PositionSize::Amount(100.0)),
+        ])
+        .look_through(&etf_holdings)
+        .await
+        .unwrap();
+        assert_eq!(look_through.holdings.len(), 2);
+        assert_eq!(
+            look_through.holdings[0].identifier.as_deref(),
+            Some("BHP.AX")
+        );
+        assert_eq!(look_through.holdings[0].amount, 150.0);
+        assert_eq!(look_through.holdings[0].via, vec!["AAA", "BBB"]);
+        assert_eq!(look_through.holdings[1].identifier.as_deref(), Some("BHP"));
+        assert_eq!(look_through.holdings[1].amount, 50.0);
+    }
+}
@@ -0,0 +1,38 @@
+//! JSON Schemas of the types other services can read back, see `SCHEMA_VERSION`
+
+use schemars::schema_for;
+use serde_json::Value;
+
+use crate::types::{ETFListItem, ETF};
+
+/// JSON Schema of a serialized `ETF` (with its holdings).
+pub fn etf_json_schema() -> Value {
+    serde_json::to_value(schema_for!(ETF)).expect("JSON Schemas always serialize")
+}
+
+/// JSON Schema of a serialized `ETFListItem`.
+pub fn etf_list_item_json_schema() -> Value {
+    serde_json::to_value(schema_for!(ETFListItem)).expect("JSON Schemas always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checked in schema, regenerate with `cargo run -p etf_holdings_lib --example json_schema`
+    fn checked_in(file_name: &str) -> Value {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("schema")
+            .join(file_name);
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn checked_in_schemas_are_up_to_date() {
+        assert_eq!(checked_in("etf.schema.json"), etf_json_schema());
+        assert_eq!(
+            checked_in("etf_list_item.schema.json"),
+            etf_list_item_json_schema()
+        );
+    }
+}
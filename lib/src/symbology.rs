@@ -0,0 +1,147 @@
+//! Map local tickers to the symbols used by different data vendors
+
+use crate::exposure::is_derivative;
+use crate::ticker::TickerMapping;
+use crate::types::{Holding, Symbols};
+
+/// Asset classes of cash-like holdings, they're listed with the currency as their ticker
+const CASH_ASSET_CLASSES: [&str; 4] = [
+    "Cash",
+    "Cash Collateral and Margins",
+    "Cash and/or Derivatives",
+    "Money Market",
+];
+
+/// Returns the symbols for a holding's local ticker across the data vendors we know about
+///
+/// All symbols come from the (configurable) `TickerMapping`. Ticker overrides are applied first so
+/// every vendor gets the same corrected ticker, then the Yahoo suffix or the exchange codes of the
+/// other vendors are added. Vendors we don't have an exchange code for are left as `None`.
+///
+/// Examples:
+///
+/// ```ignore
+/// let bhp = vendor_symbols(&TickerMapping::default(), "BHP", "Asx - All Markets");
+/// assert_eq!(bhp.yahoo, Some("BHP.AX".to_string()));
+/// assert_eq!(bhp.bloomberg, Some("BHP AU Equity".to_string()));
+/// assert_eq!(bhp.google, Some("ASX:BHP".to_string()));
+/// assert_eq!(bhp.refinitiv, Some("BHP.AX".to_string()));
+/// ```
+pub fn vendor_symbols(
+    ticker_mapping: &TickerMapping,
+    ticker: &str,
+    exchange_name: &str,
+) -> Symbols {
+    let local = ticker_mapping.local_ticker(ticker, exchange_name);
+    let codes = ticker_mapping.vendor_codes(exchange_name);
+    Symbols {
+        local: ticker.to_string(),
+        yahoo: ticker_mapping
+            .fully_qualified_ticker(ticker, exchange_name)
+            .ok(),
+        bloomberg: codes.map(|c| format!("{} {} Equity", local, c.bloomberg)),
+        google: codes.map(|c| format!("{}:{}", c.google, local)),
+        refinitiv: codes.map(|c| format!("{}{}", local, c.refinitiv)),
+    }
+}
+
+/// Returns a globally unique identifier of a holding that's a security: its Yahoo symbol, or its
+/// Refinitiv RIC if Yahoo isn't mapped.
+///
+/// Cash, derivatives, placeholder tickers like `-` and holdings on unmapped exchanges are `None`.
+/// Their tickers (e.g. `USD` for cash) aren't unique, matching them would mix up unrelated
+/// holdings.
+pub fn security_identifier(holding: &Holding) -> Option<&str> {
+    let is_cash = CASH_ASSET_CLASSES
+        .iter()
+        .any(|class| holding.asset_class.eq_ignore_ascii_case(class));
+    let is_placeholder = !holding.symbols.local.chars().any(char::is_alphanumeric);
+    if is_cash || is_placeholder || is_derivative(holding) {
+        return None;
+    }
+    let symbols = &holding.symbols;
+    symbols.yahoo.as_deref().or(symbols.refinitiv.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn builds_every_vendor_symbol() {
+        let bhp = vendor_symbols(&TickerMapping::default(), "BHP", "Asx - All Markets");
+        assert_eq!(bhp.local, "BHP");
+        assert_eq!(bhp.yahoo.as_deref(), Some("BHP.AX"));
+        assert_eq!(bhp.bloomberg.as_deref(), Some("BHP AU Equity"));
+        assert_eq!(bhp.google.as_deref(), Some("ASX:BHP"));
+        assert_eq!(bhp.refinitiv.as_deref(), Some("BHP.AX"));
+    }
+
+    #[test]
+    fn applies_overrides_to_every_vendor() {
+        let xinyi = vendor_symbols(
+            &TickerMapping::default(),
+            "968",
+            "Hong Kong Exchanges And Clearing Ltd",
+        );
+        assert_eq!(xinyi.local, "968");
+        assert_eq!(xinyi.yahoo.as_deref(), Some("0968.HK"));
+        assert_eq!(xinyi.bloomberg.as_deref(), Some("0968 HK Equity"));
+        assert_eq!(xinyi.google.as_deref(), Some("HKG:0968"));
+        assert_eq!(xinyi.refinitiv.as_deref(), Some("0968.HK"));
+    }
+
+    #[test]
+    fn unmapped_exchange_has_no_symbols() {
+        let unknown = vendor_symbols(&TickerMapping::default(), "ABC", "Mystery Exchange");
+        assert_eq!(unknown.local, "ABC");
+        assert_eq!(unknown.yahoo, None);
+        assert_eq!(unknown.bloomberg, None);
+        assert_eq!(unknown.google, None);
+        assert_eq!(unknown.refinitiv, None);
+    }
+
+    #[test]
+    fn uses_vendor_codes_from_mapping_file() {
+        let mapping = TickerMapping::from_json(
+            r#"{
+                "exchange_suffix": { "Mystery Exchange": ".MY" },
+                "vendor_codes": {
+                    "Mystery Exchange": { "bloomberg": "MY", "google": "MYX", "refinitiv": ".KL" }
+                },
+                "ticker_override": { "ABC.MY": "ABCD.MY" }
+            }"#,
+        )
+        .unwrap();
+        let abc = vendor_symbols(&mapping, "ABC", "Mystery Exchange");
+        assert_eq!(abc.yahoo.as_deref(), Some("ABCD.MY"));
+        assert_eq!(abc.bloomberg.as_deref(), Some("ABCD MY Equity"));
+        assert_eq!(abc.google.as_deref(), Some("MYX:ABCD"));
+        assert_eq!(abc.refinitiv.as_deref(), Some("ABCD.KL"));
+    }
+
+    #[test]
+    fn only_securities_have_an_identifier() {
+        assert_eq!(
+            security_identifier(&mock::holding("AAPL", "Equity", 1.0)),
+            Some("AAPL")
+        );
+        assert_eq!(
+            security_identifier(&mock::holding("USD", "Cash", 1.0)),
+            None
+        );
+        assert_eq!(
+            security_identifier(&mock::holding("ESZ1", "Futures", 1.0)),
+            None
+        );
+
+        let mut placeholder = mock::holding("-", "Equity", 1.0);
+        placeholder.symbols = vendor_symbols(&TickerMapping::default(), "-", "NASDAQ");
+        assert_eq!(security_identifier(&placeholder), None);
+
+        let mut unmapped = mock::holding("ABC", "Equity", 1.0);
+        unmapped.symbols = vendor_symbols(&TickerMapping::default(), "ABC", "Mystery Exchange");
+        assert_eq!(security_identifier(&unmapped), None);
+    }
+}
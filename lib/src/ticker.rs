@@ -1,65 +1,87 @@
 //! Qualify tickers with exchange suffix
 
 use lazy_static::lazy_static;
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::Path;
+
+use crate::types::Error;
+
+/// The exchange name that couldn't be mapped to a suffix
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownExchange(pub String);
+
+/// Codes of an exchange in the built-in table
+struct BuiltInExchange {
+    yahoo: &'static str,
+    bloomberg: &'static str,
+    google: &'static str,
+    refinitiv: &'static str,
+}
 
 lazy_static! {
-    /// Mapping of exchange names to the suffix used in yahoo symbols
-    /// https://help.yahoo.com/kb/SLN2310.html
-    static ref YAHOO_EXCHANGE_SUFFIX: HashMap<&'static str, &'static str> = {
+    /// Mapping of exchange names (as they appear in holdings files) to the suffix used in yahoo
+    /// symbols (https://help.yahoo.com/kb/SLN2310.html) and the exchange codes of other vendors
+    static ref EXCHANGES: HashMap<&'static str, BuiltInExchange> = {
         let mut m = HashMap::new();
+        let mut add = |exchange, yahoo, bloomberg, google, refinitiv| {
+            m.insert(exchange, BuiltInExchange { yahoo, bloomberg, google, refinitiv });
+        };
         // United States
-        m.insert("New York Stock Exchange Inc.", "");
-        m.insert("NASDAQ", "");
-        m.insert("Nyse Mkt Llc", "");
-        m.insert("Cboe BZX formerly known as BATS", "");
+        add("New York Stock Exchange Inc.", "", "US", "NYSE", ".N");
+        add("NASDAQ", "", "US", "NASDAQ", ".O");
+        add("Nyse Mkt Llc", "", "US", "NYSEAMERICAN", ".A");
+        add("Cboe BZX formerly known as BATS", "", "US", "BATS", ".Z");
         // Australia
-        m.insert("Asx - All Markets", ".AX");
+        add("Asx - All Markets", ".AX", "AU", "ASX", ".AX");
         // Denmark
-        m.insert("Omx Nordic Exchange Copenhagen A/S", ".CO");
+        add("Omx Nordic Exchange Copenhagen A/S", ".CO", "DC", "CPH", ".CO");
         // United Kingdown
-        m.insert("London Stock Exchange", ".L");
+        add("London Stock Exchange", ".L", "LN", "LON", ".L");
         // Spain
-        m.insert("Bolsa De Madrid", ".MC");
+        add("Bolsa De Madrid", ".MC", "SM", "BME", ".MC");
         // Portugal
-        m.insert("Nyse Euronext - Euronext Lisbon", ".LS");
+        add("Nyse Euronext - Euronext Lisbon", ".LS", "PL", "ELI", ".LS");
         // Hong Kong
-        m.insert("Hong Kong Exchanges And Clearing Ltd", ".HK");
+        add("Hong Kong Exchanges And Clearing Ltd", ".HK", "HK", "HKG", ".HK");
         // Austria
-        m.insert("Wiener Boerse Ag", ".VI");
+        add("Wiener Boerse Ag", ".VI", "AV", "VIE", ".VI");
         // Germany
-        m.insert("Xetra", ".DE");
+        add("Xetra", ".DE", "GY", "ETR", ".DE");
         // Canada
-        m.insert("Toronto Stock Exchange", ".TO");
+        add("Toronto Stock Exchange", ".TO", "CN", "TSE", ".TO");
         // South Korea
-        m.insert("Korea Exchange (Stock Market)", ".KS");
-        m.insert("Korea Exchange (Kosdaq)", ".KQ");
+        add("Korea Exchange (Stock Market)", ".KS", "KS", "KRX", ".KS");
+        add("Korea Exchange (Kosdaq)", ".KQ", "KQ", "KOSDAQ", ".KQ");
         // New Zealand
-        m.insert("New Zealand Exchange Ltd", ".NZ");
+        add("New Zealand Exchange Ltd", ".NZ", "NZ", "NZE", ".NZ");
         // Norway
-        m.insert("Oslo Bors Asa", ".OL");
+        add("Oslo Bors Asa", ".OL", "NO", "OSL", ".OL");
         // France
-        m.insert("Nyse Euronext - Euronext Paris", ".PA");
+        add("Nyse Euronext - Euronext Paris", ".PA", "FP", "EPA", ".PA");
         // Switzerland
-        m.insert("SIX Swiss Exchange", ".SW");
+        add("SIX Swiss Exchange", ".SW", "SW", "SWX", ".S");
         // Japan
-        m.insert("Tokyo Stock Exchange", ".T");
+        add("Tokyo Stock Exchange", ".T", "JP", "TYO", ".T");
         // Israel
-        m.insert("Tel Aviv Stock Exchange", ".TA");
+        add("Tel Aviv Stock Exchange", ".TA", "IT", "TLV", ".TA");
         // Italy
-        m.insert("Borsa Italiana", ".MI");
+        add("Borsa Italiana", ".MI", "IM", "BIT", ".MI");
         // Sweden
-        m.insert("Nasdaq Omx Nordic", ".ST");
+        add("Nasdaq Omx Nordic", ".ST", "SS", "STO", ".ST");
         // Netherlands
-        m.insert("Euronext Amsterdam", ".AS");
+        add("Euronext Amsterdam", ".AS", "NA", "AMS", ".AS");
         // Belgium
-        m.insert("Nyse Euronext - Euronext Brussels", ".BR");
+        add("Nyse Euronext - Euronext Brussels", ".BR", "BB", "EBR", ".BR");
         // Finland
-        m.insert("Nasdaq Omx Helsinki Ltd.", ".HE");
+        add("Nasdaq Omx Helsinki Ltd.", ".HE", "FH", "HEL", ".HE");
         // Singapore
-        m.insert("Singapore Exchange", ".SI");
+        add("Singapore Exchange", ".SI", "SP", "SGX", ".SI");
         // Ireland
-        m.insert("Irish Stock Exchange - All Market", ".IR");
+        add("Irish Stock Exchange - All Market", ".IR", "ID", "ISE", ".I");
         m
     };
 
@@ -72,53 +94,358 @@ lazy_static! {
     };
 }
 
-/// Returns a fully qualified ticker that can be used for price information
-///
-/// We need a unique ticker for every stock across the world. Tickers aren't guaranteed to be
-/// unique internationally but they are unique within their own stock exchange.
-///
-/// Since Yahoo is prevelant source of stock information, we'll copy their solution which adds a
-/// stock exchange suffix to create a globally unique fully qualified ticker. Yahoo doesn't add a
-/// suffix for US stocks (probably because they're guaranteed within the US) but all intenational
-/// stocks have a suffix.
-///
-/// For example Yahoo `MEL.NZ` is a Kiwi company with ticker MEL listed on New Zealand Exchange
-///
-/// There are also quirky stocks that are often badly encoded or have aliases. This function will
-/// correct those mistakes as well.
+/// Exchange codes used by vendors other than Yahoo
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VendorCodes {
+    /// Bloomberg exchange code, e.g. `AU` in `BHP AU Equity`
+    pub bloomberg: String,
+    /// Google Finance exchange prefix, e.g. `ASX` in `ASX:BHP`
+    pub google: String,
+    /// Refinitiv RIC suffix, e.g. `.AX` in `BHP.AX`
+    pub refinitiv: String,
+}
+
+/// Exchange suffix, vendor code and ticker override tables used to qualify tickers
 ///
-/// For example take `0968.HK`, XINYI SOLAR HOLDINGS LTD, the ticker, 0968, is interpreted as a
-/// number in spreadsheets and "corrected" to 968. We have an override for `968.HK` -> `0968.HK`
+/// The default mapping only has the built-in tables. Extra mappings can be loaded from a JSON
+/// file, they're merged over the built-in ones so they can also fix a built-in entry.
 ///
-/// Examples:
+/// ```json
+/// {
+///     "exchange_suffix": { "Nasdaq Omx Nordic": ".ST" },
+///     "vendor_codes": {
+///         "Nasdaq Omx Nordic": { "bloomberg": "SS", "google": "STO", "refinitiv": ".ST" }
+///     },
+///     "ticker_override": { "5.HK": "0005.HK" }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TickerMapping {
+    exchange_suffix: HashMap<String, String>,
+    vendor_codes: HashMap<String, VendorCodes>,
+    ticker_override: HashMap<String, String>,
+}
+
+impl Default for TickerMapping {
+    fn default() -> Self {
+        TickerMapping {
+            exchange_suffix: (*EXCHANGES)
+                .iter()
+                .map(|(exchange, codes)| (exchange.to_string(), codes.yahoo.to_string()))
+                .collect(),
+            vendor_codes: (*EXCHANGES)
+                .iter()
+                .map(|(exchange, codes)| {
+                    let vendor_codes = VendorCodes {
+                        bloomberg: codes.bloomberg.to_string(),
+                        google: codes.google.to_string(),
+                        refinitiv: codes.refinitiv.to_string(),
+                    };
+                    (exchange.to_string(), vendor_codes)
+                })
+                .collect(),
+            ticker_override: (*TICKER_OVERRIDE)
+                .iter()
+                .map(|(alias, ticker)| (alias.to_string(), ticker.to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// Validates a string while it's deserialized
 ///
-/// ```ignore
-/// let mel = fully_qualified_ticker(
-///     &"MEL".to_string(),
-///     &"New Zealand Exchange Ltd".to_string(),
-/// );
-/// assert_eq!(mel, "MEL.NZ".to_string());
+/// Unlike `#[serde(try_from = "String")]` the error gets the position of the string itself, not
+/// the end of the map it's in.
+struct ValidatingVisitor<T>(fn(String) -> Result<T, String>);
+
+impl<'de, T> Visitor<'de> for ValidatingVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+        (self.0)(value.to_string()).map_err(E::custom)
+    }
+}
+
+/// Yahoo suffix in a mapping file, either empty (US) or a dot followed by letters
+#[derive(Debug)]
+struct Suffix(String);
+
+impl<'de> Deserialize<'de> for Suffix {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(ValidatingVisitor(Suffix::try_from))
+    }
+}
+
+impl TryFrom<String> for Suffix {
+    type Error = String;
+
+    fn try_from(suffix: String) -> Result<Self, Self::Error> {
+        let valid = suffix.is_empty()
+            || (suffix.starts_with('.')
+                && suffix.len() > 1
+                && suffix[1..].chars().all(|c| c.is_ascii_alphanumeric()));
+        if valid {
+            Ok(Suffix(suffix))
+        } else {
+            Err(format!(
+                "invalid exchange suffix \"{}\", expected \"\" or something like \".AX\"",
+                suffix
+            ))
+        }
+    }
+}
+
+/// Fully qualified ticker in a mapping file, can't be empty or contain whitespace
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Ticker(String);
+
+impl<'de> Deserialize<'de> for Ticker {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(ValidatingVisitor(Ticker::try_from))
+    }
+}
+
+impl TryFrom<String> for Ticker {
+    type Error = String;
+
+    fn try_from(ticker: String) -> Result<Self, Self::Error> {
+        if ticker.is_empty() || ticker.contains(char::is_whitespace) {
+            Err(format!("invalid ticker \"{}\"", ticker))
+        } else {
+            Ok(Ticker(ticker))
+        }
+    }
+}
+
+/// Vendor codes in a mapping file, the RIC suffix is checked like a Yahoo suffix
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VendorCodesEntry {
+    bloomberg: String,
+    google: String,
+    refinitiv: Suffix,
+}
+
+/// Format of a mapping file
 ///
-/// let xinyi = fully_qualified_ticker(
-///     &"968".to_string(),
-///     &"Hong Kong Exchanges And Clearing Ltd".to_string()
-/// );
-/// assert_eq!(xinyi, "0968.HK".to_string());
-/// ```
-pub fn fully_qualified_ticker(ticker: &String, exchange_name: &String) -> String {
-    match (*YAHOO_EXCHANGE_SUFFIX).get(exchange_name.as_str()) {
-        Some(suffix) => {
-            let full_ticker = format!("{}{}", ticker, suffix);
-
-            // Check if there is an override for this ticker
-            match (*TICKER_OVERRIDE).get(full_ticker.as_str()) {
-                Some(new_ticker) => new_ticker.to_string(),
-                None => full_ticker,
-            }
+/// Validation happens while deserializing so serde_json can tell us where the bad entry is.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TickerMappingFile {
+    #[serde(default)]
+    exchange_suffix: HashMap<String, Suffix>,
+    #[serde(default)]
+    vendor_codes: HashMap<String, VendorCodesEntry>,
+    #[serde(default)]
+    ticker_override: HashMap<Ticker, Ticker>,
+}
+
+impl TickerMapping {
+    /// Load extra mappings from a JSON file and merge them over the built-in tables.
+    ///
+    /// Errors include the path and the line/column of the offending entry.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("Can't read ticker mapping {}: {}", path.display(), err))?;
+        Self::from_json(&contents).map_err(|err| {
+            Error::from(format!(
+                "Invalid ticker mapping {}: {}",
+                path.display(),
+                err
+            ))
+        })
+    }
+
+    /// Parse extra mappings from a JSON string and merge them over the built-in tables.
+    pub(crate) fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let file: TickerMappingFile = serde_json::from_str(json)?;
+
+        let mut mapping = TickerMapping::default();
+        for (exchange, Suffix(suffix)) in file.exchange_suffix {
+            mapping.exchange_suffix.insert(exchange, suffix);
+        }
+        for (exchange, codes) in file.vendor_codes {
+            let Suffix(refinitiv) = codes.refinitiv;
+            let codes = VendorCodes {
+                bloomberg: codes.bloomberg,
+                google: codes.google,
+                refinitiv,
+            };
+            mapping.vendor_codes.insert(exchange, codes);
+        }
+        for (Ticker(alias), Ticker(ticker)) in file.ticker_override {
+            mapping.ticker_override.insert(alias, ticker);
         }
-        None => {
-            println!("Couldn't find a suffix for exchange {}.", exchange_name);
-            ticker.clone()
+        Ok(mapping)
+    }
+
+    /// Returns a fully qualified ticker that can be used for price information
+    ///
+    /// We need a unique ticker for every stock across the world. Tickers aren't guaranteed to be
+    /// unique internationally but they are unique within their own stock exchange.
+    ///
+    /// Since Yahoo is prevelant source of stock information, we'll copy their solution which adds a
+    /// stock exchange suffix to create a globally unique fully qualified ticker. Yahoo doesn't add a
+    /// suffix for US stocks (probably because they're guaranteed within the US) but all intenational
+    /// stocks have a suffix.
+    ///
+    /// For example Yahoo `MEL.NZ` is a Kiwi company with ticker MEL listed on New Zealand Exchange
+    ///
+    /// There are also quirky stocks that are often badly encoded or have aliases. This function will
+    /// correct those mistakes as well.
+    ///
+    /// For example take `0968.HK`, XINYI SOLAR HOLDINGS LTD, the ticker, 0968, is interpreted as a
+    /// number in spreadsheets and "corrected" to 968. We have an override for `968.HK` -> `0968.HK`
+    ///
+    /// Returns `Err(UnknownExchange)` when there's no suffix mapping for the exchange. Using the bare
+    /// ticker in that case would most likely pull up a different (usually American) company on Yahoo.
+    ///
+    /// Examples:
+    ///
+    /// ```ignore
+    /// let mapping = TickerMapping::default();
+    ///
+    /// let mel = mapping.fully_qualified_ticker("MEL", "New Zealand Exchange Ltd");
+    /// assert_eq!(mel, Ok("MEL.NZ".to_string()));
+    ///
+    /// let xinyi = mapping.fully_qualified_ticker("968", "Hong Kong Exchanges And Clearing Ltd");
+    /// assert_eq!(xinyi, Ok("0968.HK".to_string()));
+    ///
+    /// let unknown = mapping.fully_qualified_ticker("ABC", "Mystery Exchange");
+    /// assert_eq!(unknown, Err(UnknownExchange("Mystery Exchange".to_string())));
+    /// ```
+    pub fn fully_qualified_ticker(
+        &self,
+        ticker: &str,
+        exchange_name: &str,
+    ) -> Result<String, UnknownExchange> {
+        let suffix = self
+            .exchange_suffix
+            .get(exchange_name)
+            .ok_or_else(|| UnknownExchange(exchange_name.to_string()))?;
+        let full_ticker = format!("{}{}", ticker, suffix);
+
+        // Check if there is an override for this ticker
+        match self.ticker_override.get(&full_ticker) {
+            Some(new_ticker) => Ok(new_ticker.clone()),
+            None => Ok(full_ticker),
         }
     }
+
+    /// Returns the local ticker with ticker overrides applied, so other vendors get the same
+    /// correction as Yahoo. E.g. `968` on the Hong Kong exchange becomes `0968`.
+    ///
+    /// Overrides that move a ticker to another exchange can't be expressed as a local ticker and are
+    /// left out.
+    pub fn local_ticker(&self, ticker: &str, exchange_name: &str) -> String {
+        self.exchange_suffix
+            .get(exchange_name)
+            .and_then(|suffix| {
+                self.ticker_override
+                    .get(&format!("{}{}", ticker, suffix))
+                    .and_then(|new_ticker| new_ticker.strip_suffix(suffix.as_str()))
+            })
+            .unwrap_or(ticker)
+            .to_string()
+    }
+
+    /// Returns true if the exchange is mapped to a US exchange, i.e. one Yahoo doesn't add a
+    /// suffix for.
+    pub fn is_us_exchange(&self, exchange_name: &str) -> bool {
+        self.exchange_suffix
+            .get(exchange_name)
+            .is_some_and(|suffix| suffix.is_empty())
+    }
+
+    /// Returns the exchange codes of vendors other than Yahoo, `None` if the exchange isn't mapped.
+    pub fn vendor_codes(&self, exchange_name: &str) -> Option<&VendorCodes> {
+        self.vendor_codes.get(exchange_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_json_merges_over_defaults() {
+        let json = r#"{
+            "exchange_suffix": {"Mystery Exchange": ".MY"},
+            "ticker_override": {"ABC.MY": "ABCD.MY"}
+        }"#;
+        let mapping = TickerMapping::from_json(json).unwrap();
+        assert_eq!(
+            mapping.fully_qualified_ticker("ABC", "Mystery Exchange"),
+            Ok("ABCD.MY".to_string())
+        );
+        assert_eq!(
+            mapping.fully_qualified_ticker("BHP", "Asx - All Markets"),
+            Ok("BHP.AX".to_string())
+        );
+    }
+
+    #[test]
+    fn from_json_points_at_bad_suffix() {
+        let json = "{\n  \"exchange_suffix\": {\n    \"Mystery Exchange\": \"MY\"\n  }\n}";
+        let err = TickerMapping::from_json(json).unwrap_err();
+        assert_eq!(err.line(), 3);
+        assert!(err.to_string().contains("invalid exchange suffix \"MY\""));
+    }
+
+    #[test]
+    fn from_json_points_at_bad_ticker() {
+        let json = "{\n  \"ticker_override\": {\n    \"ABC.MY\": \"AB C.MY\"\n  }\n}";
+        let err = TickerMapping::from_json(json).unwrap_err();
+        assert_eq!(err.line(), 3);
+        assert!(err.to_string().contains("invalid ticker \"AB C.MY\""));
+    }
+
+    #[test]
+    fn from_json_points_at_bad_override_alias() {
+        let json =
+            "{\n  \"ticker_override\": {\n    \"ABC.MY\": \"ABC.MY\",\n    \"\": \"X\"\n  }\n}";
+        let err = TickerMapping::from_json(json).unwrap_err();
+        assert_eq!(err.line(), 4);
+        assert!(err.to_string().contains("invalid ticker \"\""));
+    }
+
+    #[test]
+    fn from_json_points_at_unknown_field() {
+        let json = "{\n  \"exchange_suffix\": {},\n  \"overrides\": {}\n}";
+        let err = TickerMapping::from_json(json).unwrap_err();
+        assert_eq!(err.line(), 3);
+    }
+
+    #[test]
+    fn from_file_includes_path_and_line() {
+        let path = std::env::temp_dir().join(format!(
+            "etf_holdings_ticker_mapping_{}.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "{\n  \"exchange_suffix\": {\n    \"X\": \"bad\"\n  }\n}",
+        )
+        .unwrap();
+        let err = TickerMapping::from_file(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        let Error::Generic(message) = err else {
+            panic!("expected a generic error, got {:?}", err);
+        };
+        assert!(message.contains(&path.display().to_string()));
+        assert!(message.contains("line 3"), "{}", message);
+    }
+
+    #[test]
+    fn from_file_reports_missing_file() {
+        let path = std::env::temp_dir().join("etf_holdings_ticker_mapping_missing.json");
+        let err = TickerMapping::from_file(&path).unwrap_err();
+        assert!(matches!(err, Error::Generic(message) if message.starts_with("Can't read")));
+    }
 }
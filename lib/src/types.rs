@@ -1,56 +1,528 @@
 //! Contains different common types, structs, errors...
 
 use async_trait::async_trait;
-use serde::Serialize;
-use std::fmt::Display;
+use schemars::JsonSchema;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Display};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+use crate::ticker::TickerMapping;
+use crate::upstream::UpstreamClient;
+
+/// Version of the serialized layout of `ETF` and the types in it, bumped on breaking changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Accept snapshots this version of the library can read, from 1 up to `SCHEMA_VERSION`.
+fn deserialize_schema_version<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version == 0 || version > SCHEMA_VERSION {
+        return Err(de::Error::custom(format!(
+            "unsupported schema_version {}, expected 1 to {}",
+            version, SCHEMA_VERSION
+        )));
+    }
+    Ok(version)
+}
 
 /// ETF details including holding information
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct ETF {
+    /// `SCHEMA_VERSION` of the library that made this snapshot, newer versions are rejected
+    #[serde(deserialize_with = "deserialize_schema_version")]
+    pub schema_version: u32,
     pub ticker: String,
     pub name: String,
+    /// Currency of market values and notional values, prices are in `Holding::currency`
+    pub currency: String,
     pub last_update: String,
     pub outstanding_shares: f64,
     pub holdings: Vec<Holding>,
+    pub unresolved_holdings: Vec<UnresolvedHolding>,
+    /// Problems with individual rows of the holdings file, only used with lenient parsing
+    pub warnings: Vec<ParseWarning>,
+    /// Sanity checks of the holdings, filled in by `ETFHoldings`
+    pub validation: ValidationReport,
+}
+
+/// Result of sanity checking an ETF's holdings, see `validation::validate`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Returns true if nothing looked off.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Something implausible found in an ETF's holdings
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(tag = "kind")]
+pub enum ValidationIssue {
+    /// Weights (in %) don't add up to roughly 100
+    WeightSum {
+        weight_sum: f64,
+    },
+    /// An equity's market value doesn't match shares * price * FX rate
+    MarketValueMismatch {
+        ticker: String,
+        market_value: f64,
+        expected: f64,
+    },
+    /// Far fewer holdings than the previous snapshot
+    HoldingsCountDrop {
+        previous: usize,
+        current: usize,
+    },
+    NonPositiveOutstandingShares {
+        outstanding_shares: f64,
+    },
+}
+
+/// A row of a holdings file that couldn't be parsed as is
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ParseWarning {
+    /// Line number in the holdings file (starting at 1)
+    pub line: u64,
+    /// The raw text of the row
+    pub raw: String,
+    pub message: String,
+    /// Whether the row was left out of `ETF::holdings` (otherwise it was partially filled)
+    pub skipped: bool,
 }
 
 /// ETF Holding details
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct Holding {
+    /// The Yahoo symbol if the exchange is mapped (see `Symbols::yahoo`), the local ticker if not
     pub ticker: String,
     pub name: String,
     pub asset_class: String,
+    pub sector: Option<String>,
     pub market_value: f64,
     pub weight: f64,
-    pub notional_value: f64,
-    pub shares: f64,
-    pub price: f64,
+    pub notional_value: Option<f64>,
+    pub shares: Option<f64>,
+    pub price: Option<f64>,
     pub location: String,
     pub exchange: String,
     pub currency: String,
-    pub fx_rate: f64,
+    pub fx_rate: Option<f64>,
     pub market_currency: String,
+    pub symbols: Symbols,
 }
 
-/// Limited ETF information used for listing available ETFs
+/// Symbols for a holding across data vendors, `None` when the exchange isn't mapped for a vendor
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct Symbols {
+    /// Ticker on its own exchange, as listed by the fund manager
+    pub local: String,
+    /// Yahoo symbol, e.g. `BHP.AX`
+    pub yahoo: Option<String>,
+    /// Bloomberg ticker, e.g. `BHP AU Equity`
+    pub bloomberg: Option<String>,
+    /// Google Finance symbol, e.g. `ASX:BHP`
+    pub google: Option<String>,
+    /// Refinitiv RIC, e.g. `BHP.AX`
+    pub refinitiv: Option<String>,
+}
+
+/// A holding whose ticker couldn't be fully qualified because its exchange isn't mapped
+///
+/// The holding is still listed in `ETF::holdings` but with its bare local ticker and no
+/// `Symbols::yahoo`, the bare ticker shouldn't be used to look up prices.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct UnresolvedHolding {
+    pub ticker: String,
+    pub name: String,
+    pub exchange: String,
+}
+
+/// An exchange name that's missing a suffix mapping and the ETFs it was seen in
+#[derive(Serialize, Debug, Clone)]
+pub struct UnmappedExchange {
+    pub exchange: String,
+    pub etfs: Vec<String>,
+}
+
+/// Result of looking through an ETF, see `ETFHoldings::look_through`
+#[derive(Serialize, Debug, Clone)]
+pub struct LookThrough {
+    pub ticker: String,
+    /// Underlying holdings, the same security reached through different funds is combined
+    pub holdings: Vec<LookThroughHolding>,
+    /// Holdings that are supported ETFs but weren't expanded
+    pub unexpanded: Vec<UnexpandedFund>,
+}
+
+/// An underlying holding with its effective weight in the top level ETF
+#[derive(Serialize, Debug, Clone)]
+pub struct LookThroughHolding {
+    pub ticker: String,
+    /// Globally unique identifier (see `security_identifier`), `None` for cash and derivatives
+    pub identifier: Option<String>,
+    pub name: String,
+    pub asset_class: String,
+    /// Effective weight (%) in the top level ETF
+    pub weight: f64,
+    /// Tickers of the funds this holding is held through, empty if only held directly
+    pub via: Vec<String>,
+}
+
+/// A holding that's a supported ETF but wasn't expanded
+#[derive(Serialize, Debug, Clone)]
+pub struct UnexpandedFund {
+    pub ticker: String,
+    /// Tickers of the funds from the top level ETF down to the fund holding this one
+    pub path: Vec<String>,
+    pub reason: UnexpandedReason,
+}
+
+/// Why a fund in a look-through wasn't expanded
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind")]
+pub enum UnexpandedReason {
+    /// The fund holds itself somewhere down the chain
+    Cycle,
+    /// The fund is nested deeper than the look-through's `max_depth`
+    MaxDepth,
+    /// The fund's holdings couldn't be fetched
+    FetchFailed { error: String },
+}
+
+/// How much the holdings of a few ETFs overlap, see `overlap::overlap`
 #[derive(Serialize, Debug, Clone)]
+pub struct Overlap {
+    pub tickers: Vec<String>,
+    /// Holdings held by all of the ETFs
+    pub common_holdings: Vec<CommonHolding>,
+    /// Sum of the smallest weight (%) of each common holding
+    pub weighted_overlap: f64,
+    /// Tickers of holdings only held by one ETF, keyed by the ETF's ticker
+    pub unique_holdings: HashMap<String, Vec<String>>,
+    /// Weighted overlap (%) of each pair of ETFs, in the same order as `tickers`
+    pub matrix: Vec<Vec<f64>>,
+}
+
+/// A holding that all ETFs in an overlap have in common
+#[derive(Serialize, Debug, Clone)]
+pub struct CommonHolding {
+    pub ticker: String,
+    pub name: String,
+    /// Weight (%) in each ETF, in the same order as `Overlap::tickers`
+    pub weights: Vec<f64>,
+}
+
+/// An ETF's position in a holding, used for finding which ETFs hold a stock
+#[derive(Serialize, Debug, Clone)]
+pub struct ETFPosition {
+    pub etf_ticker: String,
+    pub etf_name: String,
+    pub holding_ticker: String,
+    pub holding_name: String,
+    /// Weight (%) of the holding in the ETF
+    pub weight: f64,
+    pub shares: Option<f64>,
+    /// `ETF::last_update` of the snapshot this position is from
+    pub last_update: String,
+}
+
+/// A mix of ETFs someone holds
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Portfolio {
+    pub positions: Vec<PortfolioPosition>,
+}
+
+/// A position in an ETF, e.g. `{"ticker": "IVV", "amount": 1000}` or `{"ticker": "IVV", "units": 3}`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PortfolioPosition {
+    pub ticker: String,
+    #[serde(flatten)]
+    pub size: PositionSize,
+}
+
+/// Size of a portfolio position
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionSize {
+    /// Value of the position in the ETF's currency, must be positive
+    Amount(f64),
+    /// Number of ETF units, must be positive
+    Units(f64),
+}
+
+/// A portfolio's exposure to underlying securities, see `Portfolio::look_through`
+#[derive(Serialize, Debug, Clone)]
+pub struct PortfolioLookThrough {
+    /// Currency of the portfolio's ETFs and the amounts, `None` for an empty portfolio
+    pub currency: Option<String>,
+    pub total_amount: f64,
+    /// Underlying securities, largest exposure first
+    pub holdings: Vec<PortfolioHolding>,
+}
+
+/// Effective exposure of a portfolio to an underlying security
+#[derive(Serialize, Debug, Clone)]
+pub struct PortfolioHolding {
+    pub ticker: String,
+    /// Globally unique identifier (see `security_identifier`), `None` for cash and derivatives
+    pub identifier: Option<String>,
+    pub name: String,
+    pub asset_class: String,
+    /// Effective amount invested in this security
+    pub amount: f64,
+    /// Weight (%) in the whole portfolio
+    pub weight: f64,
+    /// Tickers of the portfolio's ETFs this security is held through
+    pub via: Vec<String>,
+}
+
+/// How to group holdings in a breakdown
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakdownBy {
+    /// `Holding::location`
+    Country,
+    Currency,
+    MarketCurrency,
+    AssetClass,
+    Sector,
+}
+
+/// Holdings in one group of a breakdown, see `breakdown::breakdown`
+#[derive(Serialize, Debug, Clone)]
+pub struct BreakdownBucket {
+    pub key: String,
+    /// Sum of the weights (%) of the holdings
+    pub weight: f64,
+    pub market_value: f64,
+    pub holdings_count: usize,
+}
+
+/// Concentration metrics for all holdings and equities only, see `metrics::etf_metrics`
+#[derive(Serialize, Debug, Clone)]
+pub struct ETFMetrics {
+    pub all: ConcentrationMetrics,
+    pub equities: ConcentrationMetrics,
+}
+
+/// How concentrated some holdings are
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ConcentrationMetrics {
+    pub holdings_count: usize,
+    /// Combined weight (%) of the N largest holdings, keyed by N
+    pub top_weights: BTreeMap<usize, f64>,
+    /// Herfindahl-Hirschman index, the sum of squared weights (as fractions)
+    pub hhi: f64,
+    /// Number of equal weighted holdings with the same HHI (1 / HHI)
+    pub effective_holdings: f64,
+    /// Gini coefficient of the weights, 0 is equal weighted
+    pub gini: f64,
+}
+
+/// Exposure of an ETF's holdings with derivatives at notional value, see `exposure::exposure`
+#[derive(Serialize, Debug, Clone)]
+pub struct Exposure {
+    pub summary: ExposureSummary,
+    /// Largest absolute exposure first
+    pub holdings: Vec<HoldingExposure>,
+}
+
+/// Long/short exposure and leverage of an ETF
+#[derive(Serialize, Debug, Clone)]
+pub struct ExposureSummary {
+    /// Sum of the market value of all holdings
+    pub net_assets: f64,
+    pub long_exposure: f64,
+    /// Size of the short positions (positive)
+    pub short_exposure: f64,
+    /// Long plus short exposure
+    pub gross_exposure: f64,
+    /// Long minus short exposure
+    pub net_exposure: f64,
+    /// Gross exposure / net assets, e.g. 2.0 for a 2x leveraged fund
+    pub gross_leverage: f64,
+    /// Net exposure / net assets
+    pub net_leverage: f64,
+}
+
+/// Exposure of one holding, negative for short positions
+#[derive(Serialize, Debug, Clone)]
+pub struct HoldingExposure {
+    pub ticker: String,
+    pub name: String,
+    pub asset_class: String,
+    pub derivative: bool,
+    pub exposure: f64,
+    /// Exposure relative to net assets (%)
+    pub weight: f64,
+}
+
+/// File formats ETF snapshots can be exported to, see `export::export`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON object per line
+    JsonLines,
+    /// Needs the `parquet` feature
+    Parquet,
+    /// Needs the `xlsx` feature
+    Xlsx,
+}
+
+/// One holding of an ETF snapshot in an export. The fields are the columns, in order.
+#[derive(Serialize, Debug, Clone)]
+pub struct ExportRow {
+    pub etf_ticker: String,
+    pub etf_name: String,
+    /// Date of the snapshot, `ETF::last_update`
+    pub as_of: String,
+    pub ticker: String,
+    pub name: String,
+    pub asset_class: String,
+    pub sector: Option<String>,
+    pub weight: f64,
+    pub market_value: f64,
+    pub notional_value: Option<f64>,
+    pub shares: Option<f64>,
+    pub price: Option<f64>,
+    pub location: String,
+    pub exchange: String,
+    pub currency: String,
+    pub fx_rate: Option<f64>,
+    pub market_currency: String,
+    pub yahoo: Option<String>,
+    pub bloomberg: Option<String>,
+    pub google: Option<String>,
+    pub refinitiv: Option<String>,
+}
+
+/// Limited ETF information used for listing available ETFs
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
 pub struct ETFListItem {
     pub ticker: String,
     pub name: String,
 }
 
+impl ETFListItem {
+    /// Returns true if the query is in the ticker or name, ignoring case.
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        self.ticker.to_lowercase().contains(&query) || self.name.to_lowercase().contains(&query)
+    }
+}
+
+/// Options for fetching many ETFs at once, see `ETFHoldings::etf_details_many`
+#[derive(Debug, Clone)]
+pub struct BulkFetchOptions {
+    /// How many ETFs to fetch at the same time
+    pub concurrency: usize,
+}
+
+impl Default for BulkFetchOptions {
+    fn default() -> Self {
+        BulkFetchOptions { concurrency: 4 }
+    }
+}
+
+/// Result of fetching many ETFs, in the order they finished
+#[derive(Debug)]
+pub struct BulkFetch {
+    pub etfs: Vec<ETF>,
+    pub failures: Vec<FetchFailure>,
+}
+
+/// An ETF that couldn't be fetched in a bulk fetch
+#[derive(Debug)]
+pub struct FetchFailure {
+    pub ticker: String,
+    pub error: Error,
+}
+
+/// Ticker mapping shared between `ETFHoldings` and all fund managers so it can be reloaded
+pub type SharedTickerMapping = Arc<RwLock<TickerMapping>>;
+
+/// How requests to fund managers are retried, see `UpstreamClient`
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, 0 to never retry
+    pub max_retries: u32,
+    /// Upper bound of the first backoff, doubled for every retry (full jitter)
+    pub initial_backoff: Duration,
+    /// Upper bound of any backoff, also caps `Retry-After`
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Gets told about every request `UpstreamClient` makes, e.g. to record metrics
+pub trait UpstreamObserver: Send + Sync + Debug {
+    /// Called after every attempt once the body is read, `success` is false for request errors,
+    /// error statuses and bodies that failed to download
+    fn request_finished(&self, host: &str, duration: Duration, success: bool);
+}
+
+/// A fund manager set up by `ETFHoldings`, see `ETFHoldings::fund_managers`
+#[derive(Serialize, Debug, Clone)]
+pub struct FundManagerStatus {
+    pub name: String,
+    /// Number of supported ETFs
+    pub etfs: usize,
+    /// Why the fund manager couldn't be set up (its ETFs aren't supported then)
+    pub error: Option<String>,
+}
+
+/// The latest fetched snapshot of an ETF, see `ETFHoldings::snapshots`
+#[derive(Serialize, Debug, Clone)]
+pub struct SnapshotInfo {
+    pub ticker: String,
+    /// `ETF::last_update`, formatted by the fund manager
+    pub last_update: String,
+    pub holdings_count: usize,
+}
+
+/// Settings `ETFHoldings` hands to every fund manager
+#[derive(Debug, Clone)]
+pub struct ManagerContext {
+    pub ticker_mapping: SharedTickerMapping,
+    /// Client all requests to fund managers go through
+    pub upstream: Arc<UpstreamClient>,
+    /// Skip or partially fill bad rows in holdings files instead of failing the whole ETF
+    pub lenient_parsing: bool,
+}
+
 /// Each fund manager module has to implement this trait
+///
+/// `etf_details` is called concurrently for different tickers, caching fetched ETFs and
+/// coalescing requests for the same ticker is left to `ETFHoldings`.
 #[async_trait]
-pub trait FundManager: Send {
-    async fn new() -> Result<Self, Error>
+pub trait FundManager: Send + Sync {
+    async fn new(context: ManagerContext) -> Result<Self, Error>
     where
         Self: Sized;
     fn etfs_under_management(&self) -> Vec<ETFListItem>;
-    async fn etf_details(&mut self, ticker: &String) -> Result<ETF, Error>;
+    /// Host the holdings are fetched from, requests to it are rate limited together
+    fn host(&self) -> &str;
+    /// Fetch an ETF's details and holdings from the fund manager
+    async fn etf_details(&self, ticker: &str) -> Result<ETF, Error>;
 }
 
 /// Common error type
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Error {
     Generic(String),
     NotFound,
@@ -61,3 +533,76 @@ impl<T: Display> From<T> for Error {
         Error::Generic(error.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde::de::DeserializeOwned;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::mock;
+
+    /// Serialize, deserialize and serialize again, the JSON must stay the same
+    fn round_trip<T: Serialize + DeserializeOwned>(value: &T) {
+        let json = serde_json::to_value(value).unwrap();
+        let read_back: T = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&read_back).unwrap(), json);
+    }
+
+    fn etf() -> ETF {
+        let mut aapl = mock::holding("AAPL", "Equity", 99.0);
+        aapl.sector = Some("Information Technology".to_string());
+        let mut cash = mock::holding("USD", "Cash", 1.0);
+        cash.price = None;
+        cash.fx_rate = None;
+        let mut etf = mock::etf("AAA", vec![aapl, cash]);
+        etf.unresolved_holdings.push(UnresolvedHolding {
+            ticker: "ABC".to_string(),
+            name: "ABC Name".to_string(),
+            exchange: "Mystery Exchange".to_string(),
+        });
+        etf.warnings.push(ParseWarning {
+            line: 12,
+            raw: "ABC,ABC Name,Equity,-".to_string(),
+            message: "Invalid weight".to_string(),
+            skipped: true,
+        });
+        etf
+    }
+
+    #[test]
+    fn wire_types_round_trip() {
+        let etf = etf();
+        round_trip(&etf);
+        round_trip(&etf.holdings[0]);
+        round_trip(&etf.holdings[1]);
+        round_trip(&ETFListItem {
+            ticker: "AAA".to_string(),
+            name: "AAA ETF".to_string(),
+        });
+    }
+
+    #[test]
+    fn etf_list_items_match_ticker_or_name_ignoring_case() {
+        let ivv = ETFListItem {
+            ticker: "IVV".to_string(),
+            name: "iShares Core S&P 500 ETF".to_string(),
+        };
+        assert!(ivv.matches("ivv"));
+        assert!(ivv.matches("S&P"));
+        assert!(ivv.matches("core s"));
+        assert!(!ivv.matches("bond"));
+    }
+
+    #[test]
+    fn rejects_unknown_schema_versions() {
+        let mut json = serde_json::to_value(etf()).unwrap();
+        for version in [0, SCHEMA_VERSION + 1] {
+            json["schema_version"] = Value::from(version);
+            let error = serde_json::from_value::<ETF>(json.clone()).unwrap_err();
+            assert!(error.to_string().contains("unsupported schema_version"));
+        }
+        json["schema_version"] = Value::from(SCHEMA_VERSION);
+        assert!(serde_json::from_value::<ETF>(json).is_ok());
+    }
+}
@@ -0,0 +1,389 @@
+//! HTTP client for requests to fund managers, with timeouts, retries and rate limits
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, Response, StatusCode, Url};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, sleep_until, Instant};
+use tracing::{debug, instrument, warn};
+
+use crate::types::{Error, RetryPolicy, UpstreamObserver};
+
+/// Spaces out the start of requests to the same host
+#[derive(Debug)]
+pub(crate) struct HostRateLimiter {
+    interval: Option<Duration>,
+    /// Host -> earliest time the next request may start
+    next_start: Mutex<HashMap<String, Instant>>,
+}
+
+impl HostRateLimiter {
+    /// Rate limiter allowing `requests_per_second` per host, or unlimited if `None` or infinite.
+    pub(crate) fn new(requests_per_second: Option<f64>) -> HostRateLimiter {
+        HostRateLimiter {
+            interval: requests_per_second
+                .filter(|rate| *rate > 0.0 && rate.is_finite())
+                .map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            next_start: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait until a request to the host may start.
+    pub(crate) async fn wait(&self, host: &str) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let start = {
+            let mut next_start = self.next_start.lock().await;
+            let now = Instant::now();
+            let start = next_start.get(host).map_or(now, |next| (*next).max(now));
+            next_start.insert(host.to_string(), start + interval);
+            start
+        };
+        sleep_until(start).await;
+    }
+}
+
+/// Client shared by all fund managers (through `ManagerContext`).
+///
+/// Requests time out, are rate limited per host and are retried on timeouts, connection errors,
+/// interrupted bodies, 429 and 5xx responses following a `RetryPolicy`.
+#[derive(Debug)]
+pub struct UpstreamClient {
+    client: Client,
+    retry_policy: RetryPolicy,
+    rate_limiter: HostRateLimiter,
+    observer: Option<Arc<dyn UpstreamObserver>>,
+}
+
+impl UpstreamClient {
+    /// Creates a client. `requests_per_second` limits requests per host, unlimited if `None`.
+    pub fn new(
+        timeout: Duration,
+        retry_policy: RetryPolicy,
+        requests_per_second: Option<f64>,
+        observer: Option<Arc<dyn UpstreamObserver>>,
+    ) -> Result<UpstreamClient, Error> {
+        Ok(UpstreamClient {
+            client: Client::builder().timeout(timeout).build()?,
+            retry_policy,
+            rate_limiter: HostRateLimiter::new(requests_per_second),
+            observer,
+        })
+    }
+
+    /// GET a URL and return the body as text, retrying transient failures. Other error statuses
+    /// fail right away.
+    ///
+    /// Every attempt is reported to the observer once its body has been read, so errors and
+    /// timeouts while reading the body count as failures too.
+    #[instrument(skip(self))]
+    pub async fn get_text(&self, url: &str) -> Result<String, Error> {
+        let parsed_url = Url::parse(url)?;
+        let host = parsed_url.host_str().unwrap_or_default().to_string();
+
+        let mut attempt = 0;
+        loop {
+            self.rate_limiter.wait(&host).await;
+            let start = Instant::now();
+            let result = self.attempt(parsed_url.clone()).await;
+            if let Some(observer) = &self.observer {
+                observer.request_finished(&host, start.elapsed(), result.is_ok());
+            }
+            let retry_after = match result {
+                Ok(text) => {
+                    debug!(
+                        duration_ms = start.elapsed().as_millis() as u64,
+                        bytes = text.len(),
+                        "Fetched"
+                    );
+                    return Ok(text);
+                }
+                Err(AttemptError::Status(status, retry_after)) if is_transient(status) => {
+                    if attempt >= self.retry_policy.max_retries {
+                        return Err(Error::from(format!(
+                            "{} returned {} after {} attempts",
+                            url,
+                            status,
+                            attempt + 1
+                        )));
+                    }
+                    warn!(attempt, status = %status, "Transient error status, retrying");
+                    retry_after
+                }
+                Err(AttemptError::Status(status, _)) => {
+                    return Err(Error::from(format!("{} returned {}", url, status)));
+                }
+                Err(AttemptError::Request(err))
+                    if attempt < self.retry_policy.max_retries && is_transient_error(&err) =>
+                {
+                    warn!(attempt, error = %err, "Request failed, retrying");
+                    None
+                }
+                Err(AttemptError::Request(err)) => return Err(Error::from(err)),
+            };
+            sleep(self.backoff(attempt, retry_after)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Send one request and read the body of a successful response.
+    async fn attempt(&self, url: Url) -> Result<String, AttemptError> {
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(AttemptError::Request)?;
+        let status = response.status();
+        if is_error(status) {
+            return Err(AttemptError::Status(status, retry_after(&response)));
+        }
+        response.text().await.map_err(AttemptError::Request)
+    }
+
+    /// Time to wait before retry number `attempt + 1`, a random duration up to the exponential
+    /// backoff (full jitter) unless the server asked for a specific delay.
+    fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let policy = &self.retry_policy;
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(policy.max_backoff);
+        }
+        let exponential = policy
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(policy.max_backoff);
+        exponential.mul_f64(fastrand::f64())
+    }
+}
+
+/// Why an attempt at a request failed
+enum AttemptError {
+    /// An error status, with the server's `Retry-After`
+    Status(StatusCode, Option<Duration>),
+    /// The request failed, or reading the body did
+    Request(reqwest::Error),
+}
+
+/// Statuses that mean the request failed
+fn is_error(status: StatusCode) -> bool {
+    status.is_client_error() || status.is_server_error()
+}
+
+/// Statuses that are worth retrying
+fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Request errors that are worth retrying
+fn is_transient_error(err: &reqwest::Error) -> bool {
+    err.is_timeout() || err.is_connect() || err.is_request() || err.is_body()
+}
+
+/// `Retry-After` of a response
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, SystemTime::now())
+}
+
+/// Parse a `Retry-After` value, either seconds or an HTTP date. Dates in the past mean no delay.
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Remembers the outcome of every attempt
+    #[derive(Debug, Default)]
+    struct Outcomes(StdMutex<Vec<bool>>);
+
+    impl UpstreamObserver for Outcomes {
+        fn request_finished(&self, _host: &str, _duration: Duration, success: bool) {
+            self.0.lock().unwrap().push(success);
+        }
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello";
+    const UNAVAILABLE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    /// Serve the same raw HTTP response to every connection, returns the URL to request
+    async fn serve(response: &'static str) -> String {
+        serve_sequence(vec![response]).await
+    }
+
+    /// Serve raw HTTP responses to connections in turn, the last one to all later connections
+    async fn serve_sequence(responses: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for i in 0.. {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await;
+                let response = responses[i.min(responses.len() - 1)];
+                let _ = socket.write_all(response.as_bytes()).await;
+                // Dropping the socket closes the connection
+            }
+        });
+        url
+    }
+
+    fn client(outcomes: &Arc<Outcomes>, max_retries: u32) -> UpstreamClient {
+        client_with_max_backoff(outcomes, max_retries, Duration::from_millis(1))
+    }
+
+    fn client_with_max_backoff(
+        outcomes: &Arc<Outcomes>,
+        max_retries: u32,
+        max_backoff: Duration,
+    ) -> UpstreamClient {
+        let retry_policy = RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff,
+        };
+        let observer: Arc<dyn UpstreamObserver> = outcomes.clone();
+        UpstreamClient::new(Duration::from_secs(5), retry_policy, None, Some(observer)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reports_successful_attempts() {
+        let url = serve(OK).await;
+        let outcomes = Arc::new(Outcomes::default());
+        assert_eq!(client(&outcomes, 2).get_text(&url).await.unwrap(), "hello");
+        assert_eq!(*outcomes.0.lock().unwrap(), [true]);
+    }
+
+    #[tokio::test]
+    async fn reports_bodies_that_fail_to_download() {
+        // The connection is closed before the promised body is sent
+        let url =
+            serve("HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nhel").await;
+        let outcomes = Arc::new(Outcomes::default());
+        assert!(client(&outcomes, 1).get_text(&url).await.is_err());
+        assert_eq!(*outcomes.0.lock().unwrap(), [false, false]);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let url =
+            serve("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+        let outcomes = Arc::new(Outcomes::default());
+        let error = client(&outcomes, 2).get_text(&url).await.unwrap_err();
+        assert!(matches!(error, Error::Generic(msg) if msg.contains("404")));
+        assert_eq!(*outcomes.0.lock().unwrap(), [false]);
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let url = serve_sequence(vec![UNAVAILABLE, UNAVAILABLE, OK]).await;
+        let outcomes = Arc::new(Outcomes::default());
+        assert_eq!(client(&outcomes, 2).get_text(&url).await.unwrap(), "hello");
+        assert_eq!(*outcomes.0.lock().unwrap(), [false, false, true]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let url = serve(UNAVAILABLE).await;
+        let outcomes = Arc::new(Outcomes::default());
+        let error = client(&outcomes, 2).get_text(&url).await.unwrap_err();
+        assert!(
+            matches!(&error, Error::Generic(msg) if msg.contains("503") && msg.contains("after 3 attempts")),
+            "{:?}",
+            error
+        );
+        assert_eq!(outcomes.0.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn waits_as_long_as_retry_after_says() {
+        let url = serve_sequence(vec![
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            OK,
+        ])
+        .await;
+        let outcomes = Arc::new(Outcomes::default());
+        let client = client_with_max_backoff(&outcomes, 1, Duration::from_secs(5));
+        let start = Instant::now();
+        assert_eq!(client.get_text(&url).await.unwrap(), "hello");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(*outcomes.0.lock().unwrap(), [false, true]);
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_capped_by_max_backoff() {
+        let url = serve_sequence(vec![
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 3600\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            OK,
+        ])
+        .await;
+        let outcomes = Arc::new(Outcomes::default());
+        let client = client_with_max_backoff(&outcomes, 1, Duration::from_millis(50));
+        let start = Instant::now();
+        assert_eq!(client.get_text(&url).await.unwrap(), "hello");
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn parses_retry_after_seconds_and_dates() {
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").unwrap();
+        assert_eq!(
+            parse_retry_after(" 120 ", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+        assert_eq!(parse_retry_after("-5", now), None);
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_spaces_out_requests_per_host() {
+        let limiter = HostRateLimiter::new(Some(10.0));
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.wait("a.example").await;
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(300), "{:?}", elapsed);
+
+        // Other hosts have their own schedule
+        let start = Instant::now();
+        limiter.wait("b.example").await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_without_a_rate_does_not_wait() {
+        for rate in [None, Some(0.0), Some(f64::INFINITY)] {
+            let limiter = HostRateLimiter::new(rate);
+            let start = Instant::now();
+            for _ in 0..100 {
+                limiter.wait("a.example").await;
+            }
+            assert!(start.elapsed() < Duration::from_millis(50), "{:?}", rate);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Sanity checks for parsed ETF holdings
+//!
+//! Fund managers change their file formats without notice, columns move around and numbers end up
+//! in the wrong fields while still parsing fine. These checks catch holdings that don't add up.
+
+use crate::types::{ValidationIssue, ValidationReport, ETF};
+
+/// How far (in percentage points) the sum of weights can be from 100%
+const WEIGHT_SUM_TOLERANCE: f64 = 2.0;
+/// How far (relative) a market value can be from shares * price * FX rate
+const MARKET_VALUE_TOLERANCE: f64 = 0.02;
+/// A snapshot with fewer holdings than this fraction of the previous snapshot is suspicious
+const MIN_HOLDINGS_COUNT_RATIO: f64 = 0.8;
+
+/// Check that the holdings of an ETF are plausible.
+///
+/// `previous_holdings_count` is the number of holdings in the previous snapshot of the same ETF, if
+/// we've seen one.
+pub fn validate(etf: &ETF, previous_holdings_count: Option<usize>) -> ValidationReport {
+    let mut issues = Vec::new();
+
+    if etf.outstanding_shares <= 0.0 {
+        issues.push(ValidationIssue::NonPositiveOutstandingShares {
+            outstanding_shares: etf.outstanding_shares,
+        });
+    }
+
+    let weight_sum: f64 = etf.holdings.iter().map(|holding| holding.weight).sum();
+    if (weight_sum - 100.0).abs() > WEIGHT_SUM_TOLERANCE {
+        issues.push(ValidationIssue::WeightSum { weight_sum });
+    }
+
+    // Only equities are checked, bond prices are quoted per 100 of face value and derivatives
+    // don't have a meaningful market value
+    for holding in etf.holdings.iter().filter(|h| h.asset_class == "Equity") {
+        if let (Some(shares), Some(price), Some(fx_rate)) =
+            (holding.shares, holding.price, holding.fx_rate)
+        {
+            let expected = shares * price * fx_rate;
+            let difference = (holding.market_value - expected).abs();
+            if difference > holding.market_value.abs() * MARKET_VALUE_TOLERANCE {
+                issues.push(ValidationIssue::MarketValueMismatch {
+                    ticker: holding.ticker.clone(),
+                    market_value: holding.market_value,
+                    expected,
+                });
+            }
+        }
+    }
+
+    if let Some(previous) = previous_holdings_count {
+        let current = etf.holdings.len();
+        if (current as f64) < (previous as f64) * MIN_HOLDINGS_COUNT_RATIO {
+            issues.push(ValidationIssue::HoldingsCountDrop { previous, current });
+        }
+    }
+
+    ValidationReport { issues }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    fn etf() -> ETF {
+        mock::equity_etf("AAA", &[("X", 60.0), ("Y", 40.0)])
+    }
+
+    #[test]
+    fn plausible_holdings_have_no_issues() {
+        assert!(validate(&etf(), Some(2)).is_ok());
+    }
+
+    #[test]
+    fn weights_must_add_up_to_100() {
+        let mut etf = etf();
+        etf.holdings[1].weight = 30.0;
+        let report = validate(&etf, None);
+        assert_eq!(report.issues.len(), 1);
+        assert!(matches!(
+            report.issues[0],
+            ValidationIssue::WeightSum { weight_sum } if weight_sum == 90.0
+        ));
+
+        // Rounding in the holdings file is fine
+        etf.holdings[1].weight = 41.5;
+        assert!(validate(&etf, None).is_ok());
+    }
+
+    #[test]
+    fn equity_market_values_must_match_shares_times_price() {
+        let mut etf = etf();
+        etf.holdings[0].market_value *= 1.1;
+        let report = validate(&etf, None);
+        assert_eq!(report.issues.len(), 1);
+        assert!(matches!(
+            &report.issues[0],
+            ValidationIssue::MarketValueMismatch { ticker, market_value, expected }
+                if ticker == "X" && *market_value == 66000.0 && *expected == 60000.0
+        ));
+
+        // Bond prices are quoted per 100 of face value
+        etf.holdings[0].asset_class = "Fixed Income".to_string();
+        assert!(validate(&etf, None).is_ok());
+    }
+
+    #[test]
+    fn holdings_count_must_not_drop_sharply() {
+        let etf = etf();
+        let report = validate(&etf, Some(3));
+        assert_eq!(report.issues.len(), 1);
+        assert!(matches!(
+            report.issues[0],
+            ValidationIssue::HoldingsCountDrop {
+                previous: 3,
+                current: 2
+            }
+        ));
+        assert!(validate(&etf, Some(2)).is_ok());
+        assert!(validate(&etf, None).is_ok());
+    }
+
+    #[test]
+    fn outstanding_shares_must_be_positive() {
+        let mut etf = etf();
+        for outstanding_shares in [0.0, -100.0] {
+            etf.outstanding_shares = outstanding_shares;
+            let report = validate(&etf, None);
+            assert_eq!(report.issues.len(), 1);
+            assert!(matches!(
+                report.issues[0],
+                ValidationIssue::NonPositiveOutstandingShares { outstanding_shares: shares }
+                    if shares == outstanding_shares
+            ));
+        }
+    }
+}
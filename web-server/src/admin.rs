@@ -0,0 +1,101 @@
+//! Access control for endpoints that change the server's state.
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+
+/// Token admin endpoints require, from `ETF_ADMIN_TOKEN`. Without one they're disabled.
+pub struct AdminToken(pub Option<String>);
+
+impl AdminToken {
+    /// Token from the `ETF_ADMIN_TOKEN` environment variable, empty counts as unset.
+    pub fn from_env() -> AdminToken {
+        AdminToken(
+            std::env::var("ETF_ADMIN_TOKEN")
+                .ok()
+                .filter(|t| !t.is_empty()),
+        )
+    }
+}
+
+/// Request guard for admin endpoints, they need `Authorization: Bearer <ETF_ADMIN_TOKEN>`.
+///
+/// Requests are answered with 404 if admin endpoints are disabled and 401 without the token.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let expected = match request.rocket().state::<AdminToken>() {
+            Some(AdminToken(Some(token))) => token,
+            _ => return Outcome::Error((Status::NotFound, "Admin endpoints are disabled")),
+        };
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) if same_token(token, expected) => Outcome::Success(Admin),
+            _ => Outcome::Error((Status::Unauthorized, "Missing or wrong admin token")),
+        }
+    }
+}
+
+/// Compare tokens in constant time, so response times don't give away how much of it matched
+fn same_token(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+
+    use super::*;
+
+    #[post("/admin")]
+    fn admin_route(_admin: Admin) -> &'static str {
+        "ok"
+    }
+
+    async fn client(token: Option<&str>) -> Client {
+        let rocket = rocket::build()
+            .manage(AdminToken(token.map(String::from)))
+            .mount("/", routes![admin_route]);
+        Client::tracked(rocket).await.unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn disabled_without_a_token() {
+        let client = client(None).await;
+        let response = client
+            .post("/admin")
+            .header(Header::new("Authorization", "Bearer "))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn requires_the_token() {
+        let client = client(Some("secret")).await;
+        let status = |authorization: Option<&'static str>| {
+            let mut request = client.post("/admin");
+            if let Some(authorization) = authorization {
+                request = request.header(Header::new("Authorization", authorization));
+            }
+            async move { request.dispatch().await.status() }
+        };
+        assert_eq!(status(None).await, Status::Unauthorized);
+        assert_eq!(status(Some("Bearer secreT")).await, Status::Unauthorized);
+        assert_eq!(status(Some("Bearer secret2")).await, Status::Unauthorized);
+        assert_eq!(status(Some("secret")).await, Status::Unauthorized);
+        assert_eq!(status(Some("Bearer secret")).await, Status::Ok);
+    }
+}
@@ -1,15 +1,34 @@
 //! Provide a caching layer to save network requests.
 
-use std::collections::HashMap;
+use chrono::Utc;
+use etf_holdings_lib::DEFAULT_SNAPSHOT_TTL;
+use std::collections::{HashMap, VecDeque};
 use tokio::sync::RwLock;
+use tracing::debug;
 
-use crate::types::{DetailsResponse, GoodError, GoodResult, HistoricalPrices};
+use crate::metrics::record_cache_lookup;
+use crate::types::{DetailsResponse, GoodError, GoodResult, HistoricalPrices, YahooStatus};
 use crate::yahoo::fetch_historical_prices;
 
+/// How far back Yahoo fetches count towards `YahooStatus`
+pub const YAHOO_STATUS_WINDOW_SECONDS: i64 = 10 * 60;
+/// How long an FX rate is used before it's fetched again, unlike price histories they move
+const FX_RATE_TTL_SECONDS: i64 = 15 * 60;
+/// How long Yahoo answering that it doesn't know a symbol is remembered, it may be listed later
+const UNKNOWN_SYMBOL_TTL_SECONDS: i64 = 10 * 60;
+
 /// Cache for expensive to query objects.
 pub struct Cache {
-    details_cache: RwLock<HashMap<String, DetailsResponse>>,
+    /// ETF details and when they were inserted, kept as long as the library keeps fetched ETFs
+    details_cache: RwLock<HashMap<String, (i64, DetailsResponse)>>,
     prices_cache: RwLock<HashMap<String, Vec<HistoricalPrices>>>,
+    /// Latest FX rates by Yahoo symbol and when they were fetched
+    fx_rates_cache: RwLock<HashMap<String, (i64, f64)>>,
+    /// Symbols Yahoo doesn't know, when it said so and its message
+    unknown_symbols: RwLock<HashMap<String, (i64, String)>>,
+    /// Time and success of Yahoo fetches in the last `YAHOO_STATUS_WINDOW_SECONDS`, oldest first
+    yahoo_fetches: RwLock<VecDeque<(i64, bool)>>,
+    yahoo_status: RwLock<YahooStatus>,
 }
 
 impl Cache {
@@ -18,6 +37,10 @@ impl Cache {
         Cache {
             details_cache: RwLock::new(HashMap::new()),
             prices_cache: RwLock::new(HashMap::new()),
+            fx_rates_cache: RwLock::new(HashMap::new()),
+            unknown_symbols: RwLock::new(HashMap::new()),
+            yahoo_fetches: RwLock::new(VecDeque::new()),
+            yahoo_status: RwLock::new(YahooStatus::default()),
         }
     }
 
@@ -26,23 +49,15 @@ impl Cache {
         {
             let prices_cache = self.prices_cache.read().await;
             if let Some(cached) = prices_cache.get(ticker) {
-                println!("Cached prices for {}!", ticker);
+                debug!(ticker = %ticker, "Prices cache hit");
+                record_cache_lookup("prices", true);
                 return Ok(cached.clone());
             }
         }
-        println!("Prices for {} not cached :(", ticker);
-
-        let prices = {
-            match fetch_historical_prices(&ticker).await {
-                Ok(x) => x,
-                Err(err) => {
-                    return Err(GoodError::Generic(format!(
-                        "Error Yahoo::fetch_historical_prices({}): {:?}",
-                        ticker, err
-                    )))
-                }
-            }
-        };
+        debug!(ticker = %ticker, "Prices cache miss");
+        record_cache_lookup("prices", false);
+
+        let prices = self.fetch_prices(ticker).await?;
         {
             let mut prices_cache = self.prices_cache.write().await;
             prices_cache.insert(ticker.clone(), prices.clone());
@@ -51,20 +66,178 @@ impl Cache {
         Ok(prices)
     }
 
-    /// Get ETF details from cache (if available).
+    /// Fetch the latest FX rate for a Yahoo symbol like `USDAUD=X`, it's cached for
+    /// `FX_RATE_TTL_SECONDS`.
+    pub async fn fx_rate(&self, symbol: &String) -> GoodResult<f64> {
+        let now = Utc::now().timestamp();
+        if let Some(rate) = self.cached_fx_rate(symbol, now).await {
+            debug!(symbol = %symbol, "FX rate cache hit");
+            record_cache_lookup("fx_rates", true);
+            return Ok(rate);
+        }
+        debug!(symbol = %symbol, "FX rate cache miss");
+        record_cache_lookup("fx_rates", false);
+
+        let prices = self.fetch_prices(symbol).await?;
+        let latest = prices
+            .last()
+            .ok_or_else(|| GoodError::NotFound(format!("Yahoo has no prices for {}.", symbol)))?;
+        self.insert_fx_rate(symbol, latest.close, now).await;
+        Ok(latest.close)
+    }
+
+    /// An FX rate fetched less than `FX_RATE_TTL_SECONDS` before `now`.
+    async fn cached_fx_rate(&self, symbol: &str, now: i64) -> Option<f64> {
+        let fx_rates_cache = self.fx_rates_cache.read().await;
+        fx_rates_cache
+            .get(symbol)
+            .filter(|(fetched, _)| now - fetched < FX_RATE_TTL_SECONDS)
+            .map(|(_, rate)| *rate)
+    }
+
+    async fn insert_fx_rate(&self, symbol: &str, rate: f64, now: i64) {
+        let mut fx_rates_cache = self.fx_rates_cache.write().await;
+        fx_rates_cache.insert(symbol.to_string(), (now, rate));
+    }
+
+    /// Fetch the price history for a stock from Yahoo, without caching it. Symbols Yahoo doesn't
+    /// know are answered from memory for `UNKNOWN_SYMBOL_TTL_SECONDS`.
+    async fn fetch_prices(&self, ticker: &String) -> GoodResult<Vec<HistoricalPrices>> {
+        let now = Utc::now().timestamp();
+        if let Some(msg) = self.unknown_symbol(ticker, now).await {
+            debug!(ticker = %ticker, "Known to be unknown to Yahoo");
+            return Err(GoodError::NotFound(msg));
+        }
+
+        let result = fetch_historical_prices(ticker).await;
+        // Yahoo answering that it doesn't know a symbol still means it's up
+        self.record_yahoo_fetch(!matches!(result, Err(GoodError::Generic(_))))
+            .await;
+        match result {
+            Ok(x) => Ok(x),
+            Err(GoodError::NotFound(msg)) => {
+                self.insert_unknown_symbol(ticker, &msg, now).await;
+                Err(GoodError::NotFound(msg))
+            }
+            Err(err) => Err(GoodError::Generic(format!(
+                "Error Yahoo::fetch_historical_prices({}): {:?}",
+                ticker, err
+            ))),
+        }
+    }
+
+    /// Yahoo's message for a symbol it said it doesn't know less than `UNKNOWN_SYMBOL_TTL_SECONDS`
+    /// before `now`.
+    async fn unknown_symbol(&self, symbol: &str, now: i64) -> Option<String> {
+        let unknown_symbols = self.unknown_symbols.read().await;
+        unknown_symbols
+            .get(symbol)
+            .filter(|(time, _)| now - time < UNKNOWN_SYMBOL_TTL_SECONDS)
+            .map(|(_, msg)| msg.clone())
+    }
+
+    async fn insert_unknown_symbol(&self, symbol: &str, msg: &str, now: i64) {
+        let mut unknown_symbols = self.unknown_symbols.write().await;
+        unknown_symbols.insert(symbol.to_string(), (now, msg.to_string()));
+    }
+
+    /// Remember the outcome of a Yahoo fetch for `yahoo_status`.
+    async fn record_yahoo_fetch(&self, success: bool) {
+        let now = Utc::now().timestamp();
+        {
+            let mut yahoo_status = self.yahoo_status.write().await;
+            if success {
+                yahoo_status.last_success = Some(now);
+            } else {
+                yahoo_status.last_error = Some(now);
+            }
+        }
+        let mut yahoo_fetches = self.yahoo_fetches.write().await;
+        forget_old_fetches(&mut yahoo_fetches, now);
+        yahoo_fetches.push_back((now, success));
+    }
+
+    /// When fetching prices from Yahoo last worked and failed, and how often it failed lately.
+    pub async fn yahoo_status(&self) -> YahooStatus {
+        let mut yahoo_fetches = self.yahoo_fetches.write().await;
+        forget_old_fetches(&mut yahoo_fetches, Utc::now().timestamp());
+        let mut yahoo_status = self.yahoo_status.read().await.clone();
+        yahoo_status.recent_fetches = yahoo_fetches.len();
+        yahoo_status.recent_failures = yahoo_fetches.iter().filter(|(_, ok)| !ok).count();
+        yahoo_status
+    }
+
+    /// Get ETF details from cache (if available and younger than `DEFAULT_SNAPSHOT_TTL`).
     pub async fn get_details(&self, ticker: &String) -> Option<DetailsResponse> {
         let details_cache = self.details_cache.read().await;
-        if let Some(cached) = details_cache.get(ticker) {
-            println!("Cached details for {}!", ticker);
+        let since = Utc::now().timestamp() - DEFAULT_SNAPSHOT_TTL.as_secs() as i64;
+        let fresh = details_cache.get(ticker).filter(|(time, _)| *time >= since);
+        if let Some((_, cached)) = fresh {
+            debug!(ticker = %ticker, "Details cache hit");
+            record_cache_lookup("details", true);
             return Some(cached.clone());
         }
-        println!("Details for {} not cached :(", ticker);
+        debug!(ticker = %ticker, "Details cache miss");
+        record_cache_lookup("details", false);
         None
     }
 
+    /// Forget all ETF details.
+    pub async fn clear_details(&self) {
+        self.details_cache.write().await.clear();
+    }
+
     /// Set ETF details.
-    pub async fn insert_details(&self, ticker: &String, details: &DetailsResponse) {
+    pub async fn insert_details(&self, ticker: &str, details: &DetailsResponse) {
         let mut details_cache = self.details_cache.write().await;
-        details_cache.insert(ticker.clone(), details.clone());
+        details_cache.insert(
+            ticker.to_string(),
+            (Utc::now().timestamp(), details.clone()),
+        );
+    }
+}
+
+/// Drop Yahoo fetches from before the `YAHOO_STATUS_WINDOW_SECONDS` up to `now`.
+fn forget_old_fetches(yahoo_fetches: &mut VecDeque<(i64, bool)>, now: i64) {
+    let since = now - YAHOO_STATUS_WINDOW_SECONDS;
+    while yahoo_fetches.front().is_some_and(|(time, _)| *time < since) {
+        yahoo_fetches.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn fx_rates_are_fetched_again_once_they_expire() {
+        let cache = Cache::new().await;
+        let symbol = "USDAUD=X";
+        cache.insert_fx_rate(symbol, 1.35, 1000).await;
+        assert_eq!(cache.cached_fx_rate(symbol, 1000).await, Some(1.35));
+        let later = 1000 + FX_RATE_TTL_SECONDS;
+        assert_eq!(cache.cached_fx_rate(symbol, later - 1).await, Some(1.35));
+        assert_eq!(cache.cached_fx_rate(symbol, later).await, None);
+
+        // What `fx_rate` does after the miss
+        cache.insert_fx_rate(symbol, 1.42, later).await;
+        assert_eq!(cache.cached_fx_rate(symbol, later).await, Some(1.42));
+        assert_eq!(cache.cached_fx_rate("USDEUR=X", later).await, None);
+    }
+
+    #[rocket::async_test]
+    async fn unknown_symbols_are_remembered_for_a_while() {
+        let cache = Cache::new().await;
+        cache
+            .insert_unknown_symbol("ZZZ", "No data found", 1000)
+            .await;
+        assert_eq!(
+            cache.unknown_symbol("ZZZ", 1000).await.as_deref(),
+            Some("No data found")
+        );
+        let later = 1000 + UNKNOWN_SYMBOL_TTL_SECONDS;
+        assert!(cache.unknown_symbol("ZZZ", later - 1).await.is_some());
+        assert_eq!(cache.unknown_symbol("ZZZ", later).await, None);
+        assert_eq!(cache.unknown_symbol("AAPL", 1000).await, None);
     }
 }
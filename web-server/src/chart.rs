@@ -4,6 +4,7 @@ use etf_holdings_lib::ETFHoldings;
 use std::collections::HashMap;
 use std::iter::Peekable;
 use std::slice::Iter;
+use tracing::debug;
 
 use crate::cache::Cache;
 use crate::details::details_response;
@@ -35,7 +36,7 @@ fn merge_chart_prices(details: &DetailsResponse) -> GoodResult<Vec<ChartPrice>>
             .values_mut()
             // This bit clones the timestamp to avoid borrowing otherwise we won't be able borrow
             // again for the for loop
-            .filter_map(|x| Some(x.peek()?.timestamp.clone()))
+            .filter_map(|x| Some(x.peek()?.timestamp))
             .min();
 
         // We'll see a timestamp here until all iters have been exhausted
@@ -76,11 +77,11 @@ fn merge_chart_prices(details: &DetailsResponse) -> GoodResult<Vec<ChartPrice>>
 
 /// Create a price history chart.
 /// * The Y-axis is the historical price as a percentage of first date's price (ETF's price is 100,
-/// each holding's price is equal to their weight)
+///   each holding's price is equal to their weight)
 /// * The X-axis is the date
 fn create_price_chart(details: &DetailsResponse) -> GoodResult<Vec<ChartPrice>> {
     let mut prices = merge_chart_prices(details)?;
-    prices.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    prices.sort_by_key(|a| a.timestamp);
 
     let first_price = prices.first().unwrap().clone();
     let last_price = prices.last().unwrap().clone();
@@ -117,9 +118,8 @@ pub async fn chart_response(
     etf_holdings: &ETFHoldings,
     ticker: &String,
 ) -> GoodResult<ChartResponse> {
-    println!("Chart {}: Loading details.", ticker);
-    let details = details_response(cache, etf_holdings, ticker).await?;
-    println!("Chart {}: Details loaded.", ticker);
+    let details = details_response(cache, etf_holdings, ticker, None).await?;
+    debug!("Details loaded");
 
     let mut holding_details: HashMap<String, ChartHoldingDetails> = HashMap::new();
     for holding in &details.equity_holdings {
@@ -132,7 +132,6 @@ pub async fn chart_response(
         );
     }
 
-    println!("Chart {}: Merging prices.", ticker);
     let chart = create_price_chart(&details)?;
 
     let result = ChartResponse {
@@ -141,6 +140,10 @@ pub async fn chart_response(
         holding_details,
         chart,
     };
-    println!("Chart {}: Final results\n{:#?}", ticker, result);
+    debug!(
+        holdings = result.holding_details.len(),
+        prices = result.chart.len(),
+        "Merged prices"
+    );
     Ok(result)
 }
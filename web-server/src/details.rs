@@ -1,42 +1,116 @@
 //! Module used for constructing DetailsResponse.
 
-use etf_holdings_lib::{ETFHoldings, Error as ETFErr};
+use chrono::NaiveDate;
+use etf_holdings_lib::{
+    convert_etf, etf_metrics, exposure, implied_nav, premium_discount, ETFHoldings,
+    FallbackFxRates, FundFxRates, FxRateSource, ETF,
+};
 use std::collections::HashMap;
 
 use crate::cache::Cache;
-use crate::types::{DetailsEquityHolding, DetailsResponse, GoodError, GoodResult};
+use crate::types::{
+    etf_details_error, DetailsEquityHolding, DetailsNav, DetailsResponse, GoodError, GoodResult,
+    HistoricalPrices,
+};
+use crate::yahoo::YahooFxRates;
+
+/// Convert an ETF to another currency with the fund's own FX rates, or Yahoo's for currencies the
+/// fund doesn't hold anything in. Also returns the rate from the ETF's currency to `currency`.
+async fn convert_currency(cache: &Cache, etf: &ETF, currency: &str) -> GoodResult<(ETF, f64)> {
+    if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(GoodError::BadRequest(format!(
+            "Invalid currency {}, expected a code like AUD.",
+            currency
+        )));
+    }
+
+    let fund_rates = FundFxRates::new(etf);
+    let yahoo_rates = YahooFxRates { cache };
+    let rates = FallbackFxRates {
+        primary: &fund_rates,
+        fallback: &yahoo_rates,
+    };
+    let rate = rates
+        .rate(&etf.currency, currency)
+        .await
+        .map_err(|err| GoodError::Generic(format!("Error FxRateSource::rate(): {:?}", err)))?;
+    let converted = convert_etf(etf, currency, &rates)
+        .await
+        .map_err(|err| GoodError::Generic(format!("Error convert_etf(): {:?}", err)))?;
+    Ok((converted, rate))
+}
+
+/// Timestamp (midnight UTC) of the date of a holdings snapshot, see `ETF::last_update`.
+pub fn last_update_timestamp(last_update: &str) -> Option<i64> {
+    // iShares dates look like "Oct 15, 2021"
+    NaiveDate::parse_from_str(last_update, "%b %d, %Y")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc().timestamp())
+}
+
+/// Compare the implied NAV from holdings with the ETF's market price.
+///
+/// The market price is the close on the date the holdings are from, or the closest close before
+/// it. If the holdings are newer than the price history the latest close is used. There's no market
+/// price if the holdings predate the price history or their date is unknown, comparing with a later
+/// price would report a made up premium/discount.
+fn nav_details(
+    implied_nav: Option<f64>,
+    last_update: &str,
+    prices: Option<&Vec<HistoricalPrices>>,
+    price_rate: f64,
+) -> DetailsNav {
+    let holdings_timestamp = last_update_timestamp(last_update);
+    let market_price = prices.and_then(|prices| {
+        holdings_timestamp.and_then(|ts| prices.iter().rev().find(|price| price.timestamp <= ts))
+    });
+    let market_price_close = market_price.map(|price| price.close * price_rate);
+
+    DetailsNav {
+        implied_nav,
+        market_price: market_price_close,
+        market_price_timestamp: market_price.map(|price| price.timestamp),
+        premium_discount: implied_nav
+            .zip(market_price_close)
+            .map(|(nav, price)| premium_discount(nav, price)),
+    }
+}
 
 /// Details response includes full ETF/holding details and price history.
 ///
 /// The response is cached to save repeat network requests. All fetched prices are cached separate
 /// to be used for other ETFs too.
+///
+/// With a `currency` (case insensitive) the ETF's values are converted to that currency. Price
+/// histories stay in the currency the securities trade in. Converted responses aren't cached since
+/// FX rates move, they're worked out from the cached ETF and prices each time.
 pub async fn details_response(
     cache: &Cache,
     etf_holdings: &ETFHoldings,
     ticker: &String,
+    currency: Option<&str>,
 ) -> GoodResult<DetailsResponse> {
-    if let Some(response) = cache.get_details(ticker).await {
-        return Ok(response);
+    let currency = currency.map(|currency| currency.to_ascii_uppercase());
+    if currency.is_none() {
+        if let Some(response) = cache.get_details(ticker).await {
+            return Ok(response);
+        }
     }
 
-    let etf = {
-        match etf_holdings.etf_details(ticker).await {
-            Ok(x) => x,
-            Err(ETFErr::NotFound) => {
-                return Err(GoodError::NotFound(format!(
-                    "Can't find {} in ETFHoldings.",
-                    ticker
-                )))
-            }
-            Err(ETFErr::Generic(msg)) => {
-                return Err(GoodError::Generic(format!(
-                    "Error ETFHoldings::etf_details({}): {:?}",
-                    ticker, msg
-                )))
-            }
-        }
+    let etf = etf_holdings
+        .etf_details(ticker)
+        .await
+        .map_err(|err| etf_details_error(ticker, err))?;
+    let (etf, price_rate) = match &currency {
+        Some(currency) => convert_currency(cache, &etf, currency).await?,
+        None => (etf, 1.0),
     };
 
+    let metrics = etf_metrics(&etf);
+    let implied_nav = implied_nav(&etf);
+    let exposure = exposure(&etf).summary;
+
     let mut equity_holdings = Vec::new();
     let mut other_holdings = HashMap::new();
     for holding in etf.holdings {
@@ -49,7 +123,12 @@ pub async fn details_response(
             //         None
             //     }
             // };
-            let prices = cache.prices(&holding.ticker).await.ok();
+            // Holdings on unmapped exchanges have no Yahoo symbol, their bare ticker would fetch
+            // some other company's prices
+            let prices = match &holding.symbols.yahoo {
+                Some(yahoo) => cache.prices(yahoo).await.ok(),
+                None => None,
+            };
 
             equity_holdings.push(DetailsEquityHolding {
                 ticker: holding.ticker,
@@ -57,6 +136,7 @@ pub async fn details_response(
                 weight: holding.weight,
                 location: holding.location,
                 exchange: holding.exchange,
+                symbols: holding.symbols,
                 prices,
             });
         } else {
@@ -68,14 +148,81 @@ pub async fn details_response(
         }
     }
 
-    let prices = cache.prices(&ticker).await.ok();
+    let prices = cache.prices(ticker).await.ok();
+    let nav = nav_details(implied_nav, &etf.last_update, prices.as_ref(), price_rate);
     let response = DetailsResponse {
         ticker: etf.ticker,
         name: etf.name,
+        currency: etf.currency,
         equity_holdings,
         other_holdings,
         prices,
+        nav,
+        exposure,
+        metrics,
+        warnings: etf.warnings,
+        validation: etf.validation,
     };
-    cache.insert_details(ticker, &response).await;
+    if currency.is_none() {
+        cache.insert_details(ticker, &response).await;
+    }
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(date: &str, close: f64) -> HistoricalPrices {
+        HistoricalPrices {
+            timestamp: last_update_timestamp(date).unwrap(),
+            volume: 0,
+            open: close,
+            low: close,
+            high: close,
+            close,
+            adjclose: close,
+        }
+    }
+
+    #[test]
+    fn market_price_is_the_close_on_or_before_the_holdings_date() {
+        let prices = vec![
+            price("Oct 13, 2021", 99.0),
+            price("Oct 14, 2021", 101.0),
+            price("Oct 18, 2021", 103.0),
+        ];
+
+        let nav = nav_details(Some(100.0), "Oct 16, 2021", Some(&prices), 1.0);
+        assert_eq!(nav.market_price, Some(101.0));
+        assert_eq!(nav.premium_discount.map(|p| p.round()), Some(1.0));
+
+        let nav = nav_details(Some(100.0), "Oct 20, 2021", Some(&prices), 2.0);
+        assert_eq!(nav.market_price, Some(206.0));
+    }
+
+    #[test]
+    fn no_market_price_before_the_price_history() {
+        let prices = vec![price("Oct 13, 2021", 99.0)];
+        for last_update in ["Oct 12, 2021", "not a date"] {
+            let nav = nav_details(Some(100.0), last_update, Some(&prices), 1.0);
+            assert_eq!(nav.market_price, None);
+            assert_eq!(nav.market_price_timestamp, None);
+            assert_eq!(nav.premium_discount, None);
+        }
+    }
+
+    #[test]
+    fn no_premium_discount_without_a_price_or_nav() {
+        let nav = nav_details(Some(100.0), "Oct 16, 2021", None, 1.0);
+        assert_eq!(nav.implied_nav, Some(100.0));
+        assert_eq!(nav.market_price, None);
+        assert_eq!(nav.premium_discount, None);
+
+        // ETFs without outstanding shares have no implied NAV
+        let prices = vec![price("Oct 14, 2021", 101.0)];
+        let nav = nav_details(None, "Oct 16, 2021", Some(&prices), 1.0);
+        assert_eq!(nav.market_price, Some(101.0));
+        assert_eq!(nav.premium_discount, None);
+    }
+}
@@ -0,0 +1,55 @@
+//! Module used for constructing ReadyResponse.
+
+use etf_holdings_lib::ETFHoldings;
+
+use crate::cache::Cache;
+use crate::types::{ReadyResponse, YahooStatus};
+
+/// Failed Yahoo fetches in the status window before Yahoo can count as down, so one bad request
+/// doesn't take the server out of rotation
+const MIN_YAHOO_FAILURES: usize = 3;
+
+/// Yahoo counts as down if at least half of its recent fetches failed (and enough of them did).
+/// Failures older than the status window are forgotten, no recent fetches is fine.
+fn yahoo_ok(yahoo: &YahooStatus) -> bool {
+    yahoo.recent_failures < MIN_YAHOO_FAILURES || yahoo.recent_failures * 2 < yahoo.recent_fetches
+}
+
+/// Readiness of the server, built from the state of `ETFHoldings` and `Cache`.
+pub async fn ready_response(cache: &Cache, etf_holdings: &ETFHoldings) -> ReadyResponse {
+    let etfs = etf_holdings.etf_list().await.len();
+    let fund_managers = etf_holdings.fund_managers().await;
+    let yahoo = cache.yahoo_status().await;
+
+    let ready = etfs > 0 && fund_managers.iter().all(|m| m.error.is_none()) && yahoo_ok(&yahoo);
+
+    ReadyResponse {
+        ready,
+        etfs,
+        fund_managers,
+        yahoo,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yahoo(recent_fetches: usize, recent_failures: usize) -> YahooStatus {
+        YahooStatus {
+            recent_fetches,
+            recent_failures,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn yahoo_is_down_when_most_recent_fetches_fail() {
+        assert!(yahoo_ok(&yahoo(0, 0)));
+        // A few failures aren't enough
+        assert!(yahoo_ok(&yahoo(2, 2)));
+        assert!(yahoo_ok(&yahoo(20, 9)));
+        assert!(!yahoo_ok(&yahoo(3, 3)));
+        assert!(!yahoo_ok(&yahoo(20, 10)));
+    }
+}
@@ -0,0 +1,66 @@
+//! Tracing setup and request logging.
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use std::time::Instant;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+
+use crate::metrics::REQUEST_DURATION;
+
+/// Log to stdout, filtered with `RUST_LOG`. Set `LOG_FORMAT=json` for one JSON object per line.
+///
+/// Rocket's own log messages are passed on to tracing as well. By default its per request
+/// messages are left out, `RequestLogger` logs a line per request instead.
+pub fn init_tracing() {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("info,rocket::server=warn"));
+    let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+    if std::env::var("LOG_FORMAT").is_ok_and(|format| format == "json") {
+        subscriber.json().init();
+    } else {
+        subscriber.init();
+    }
+}
+
+/// When a request started, kept in the request's local cache
+struct RequestStart(Instant);
+
+/// Fairing logging every request with its status and duration, which is also recorded in
+/// `REQUEST_DURATION`
+pub struct RequestLogger;
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request logger",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let duration = request
+            .local_cache(|| RequestStart(Instant::now()))
+            .0
+            .elapsed();
+        let route = request
+            .route()
+            .map_or("unmatched".to_string(), |route| route.uri.to_string());
+        let status = response.status().code;
+        REQUEST_DURATION
+            .with_label_values(&[request.method().as_str(), &route, &status.to_string()])
+            .observe(duration.as_secs_f64());
+        info!(
+            method = %request.method(),
+            uri = %request.uri(),
+            status,
+            duration_ms = duration.as_millis() as u64,
+            "Request"
+        );
+    }
+}
@@ -1,56 +1,297 @@
 #[macro_use]
 extern crate rocket;
 
-use etf_holdings_lib::{ETFHoldings, ETFListItem};
+use etf_holdings_lib::{
+    breakdown, export, exposure, BreakdownBucket, BreakdownBy, ETFHoldings, ETFHoldingsOptions,
+    ETFListItem, ETFPosition, Error as ETFErr, ExportFormat, Exposure, Overlap, Portfolio,
+    PortfolioLookThrough, UnmappedExchange,
+};
+use prometheus::TextEncoder;
+use rocket::http::{ContentType, Status};
 use rocket::serde::json::Json;
 use rocket::State;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::instrument;
 
+mod admin;
 mod cache;
 mod chart;
 mod details;
+mod health;
+mod logging;
+mod metrics;
+mod overlap;
 mod types;
 mod yahoo;
+use admin::{Admin, AdminToken};
 use cache::Cache;
 use chart::chart_response;
-use details::details_response;
-use types::{ChartResponse, DetailsResponse, GoodResult};
+use details::{details_response, last_update_timestamp};
+use health::ready_response;
+use logging::{init_tracing, RequestLogger};
+use metrics::{UpstreamMetrics, NEWEST_SNAPSHOT_TIMESTAMP, SUPPORTED_ETFS};
+use overlap::overlap_response;
+use types::{
+    etf_details_error, to_good_error, ChartResponse, DetailsResponse, GoodError, GoodResult,
+    HealthResponse, ReadyResponse,
+};
+
+/// How often fund managers that couldn't be set up are tried again
+const MANAGER_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
 /// Handler for the list endpoint.
 #[get("/etf/list")]
-async fn list_handler(etf_holdings: &State<ETFHoldings>) -> Json<Vec<ETFListItem>> {
+#[instrument(skip_all)]
+async fn list_handler(etf_holdings: &State<Arc<ETFHoldings>>) -> Json<Vec<ETFListItem>> {
     Json(etf_holdings.etf_list().await)
 }
 
-/// Handler for the details endpoint.
-#[get("/etf/<ticker>")]
+/// Handler for the endpoint listing (fetched) ETFs that hold a stock.
+#[get("/holding/<ticker>/etfs")]
+#[instrument(skip_all, fields(ticker = %ticker))]
+async fn holding_etfs_handler(
+    etf_holdings: &State<Arc<ETFHoldings>>,
+    ticker: String,
+) -> Json<Vec<ETFPosition>> {
+    Json(etf_holdings.etfs_holding(&ticker).await)
+}
+
+/// Handler for the overlap endpoint, e.g. `/overlap?tickers=IVV,QQQ`.
+#[get("/overlap?<tickers>")]
+#[instrument(skip_all, fields(tickers = %tickers))]
+async fn overlap_handler(
+    etf_holdings: &State<Arc<ETFHoldings>>,
+    tickers: String,
+) -> GoodResult<Json<Overlap>> {
+    Ok(Json(overlap_response(etf_holdings, &tickers).await?))
+}
+
+/// Handler for the portfolio look-through endpoint.
+#[post("/portfolio/lookthrough", data = "<portfolio>")]
+#[instrument(skip_all)]
+async fn portfolio_look_through_handler(
+    etf_holdings: &State<Arc<ETFHoldings>>,
+    portfolio: Json<Portfolio>,
+) -> GoodResult<Json<PortfolioLookThrough>> {
+    portfolio.validate().map_err(GoodError::BadRequest)?;
+    let look_through = portfolio.look_through(etf_holdings).await.map_err(|err| {
+        let tickers: Vec<&str> = portfolio
+            .positions
+            .iter()
+            .map(|position| position.ticker.as_str())
+            .collect();
+        etf_details_error(&tickers.join(","), err)
+    })?;
+    Ok(Json(look_through))
+}
+
+/// Handler for the unmapped exchanges endpoint.
+#[get("/exchanges/unmapped")]
+#[instrument(skip_all)]
+async fn unmapped_exchanges_handler(
+    etf_holdings: &State<Arc<ETFHoldings>>,
+) -> Json<Vec<UnmappedExchange>> {
+    Json(etf_holdings.unmapped_exchanges().await)
+}
+
+/// Handler for reloading the ticker mapping file.
+///
+/// Needs the admin token (see `Admin`). Cached details were built with the old mapping so they're
+/// dropped too. A bad mapping file is a 422 with the error (pointing at the offending line) in the
+/// body, the old mapping stays in use.
+#[post("/ticker_mapping/reload")]
+#[instrument(skip_all)]
+async fn reload_ticker_mapping_handler(
+    _admin: Admin,
+    cache: &State<Cache>,
+    etf_holdings: &State<Arc<ETFHoldings>>,
+) -> GoodResult<()> {
+    etf_holdings
+        .reload_ticker_mapping()
+        .await
+        .map_err(|err| match err {
+            ETFErr::Generic(msg) => GoodError::Unprocessable(msg),
+            ETFErr::NotFound => GoodError::Unprocessable("Ticker mapping not found.".to_string()),
+        })?;
+    cache.clear_details().await;
+    Ok(())
+}
+
+/// Handler for the details endpoint, `?currency=AUD` converts values to another currency.
+#[get("/etf/<ticker>?<currency>")]
+#[instrument(skip_all, fields(ticker = %ticker, currency = ?currency))]
 async fn details_handler(
     cache: &State<Cache>,
-    etf_holdings: &State<ETFHoldings>,
+    etf_holdings: &State<Arc<ETFHoldings>>,
     ticker: String,
+    currency: Option<String>,
 ) -> GoodResult<Json<DetailsResponse>> {
     Ok(Json(
-        details_response(&cache, &etf_holdings, &ticker).await?,
+        details_response(cache, etf_holdings, &ticker, currency.as_deref()).await?,
     ))
 }
 
+/// Handler for the breakdown endpoint, e.g. `/etf/IVV/breakdown?by=sector`.
+#[get("/etf/<ticker>/breakdown?<by>")]
+#[instrument(skip_all, fields(ticker = %ticker, by = %by))]
+async fn breakdown_handler(
+    etf_holdings: &State<Arc<ETFHoldings>>,
+    ticker: String,
+    by: String,
+) -> GoodResult<Json<Vec<BreakdownBucket>>> {
+    let by: BreakdownBy = by.parse().map_err(GoodError::BadRequest)?;
+    let etf = etf_holdings
+        .etf_details(&ticker)
+        .await
+        .map_err(|err| etf_details_error(&ticker, err))?;
+    Ok(Json(breakdown(&etf, by)))
+}
+
+/// Handler for downloading an ETF's holdings as CSV, with the columns of `ExportRow`.
+#[get("/etf/<ticker>/holdings.csv")]
+#[instrument(skip_all, fields(ticker = %ticker))]
+async fn holdings_csv_handler(
+    etf_holdings: &State<Arc<ETFHoldings>>,
+    ticker: String,
+) -> GoodResult<(ContentType, Vec<u8>)> {
+    let etf = etf_holdings
+        .etf_details(&ticker)
+        .await
+        .map_err(|err| etf_details_error(&ticker, err))?;
+    let mut csv = Vec::new();
+    export(&[etf], ExportFormat::Csv, &mut csv)
+        .map_err(|err| GoodError::Generic(format!("Error export(): {:?}", err)))?;
+    Ok((ContentType::CSV, csv))
+}
+
+/// Handler for the exposure endpoint, derivatives are counted at their notional value.
+#[get("/etf/<ticker>/exposure")]
+#[instrument(skip_all, fields(ticker = %ticker))]
+async fn exposure_handler(
+    etf_holdings: &State<Arc<ETFHoldings>>,
+    ticker: String,
+) -> GoodResult<Json<Exposure>> {
+    let etf = etf_holdings
+        .etf_details(&ticker)
+        .await
+        .map_err(|err| etf_details_error(&ticker, err))?;
+    Ok(Json(exposure(&etf)))
+}
+
 /// Handler for the chart endpoint.
 #[get("/etf_chart/<ticker>")]
+#[instrument(skip_all, fields(ticker = %ticker))]
 async fn chart_handler(
     cache: &State<Cache>,
-    etf_holdings: &State<ETFHoldings>,
+    etf_holdings: &State<Arc<ETFHoldings>>,
     ticker: String,
 ) -> GoodResult<Json<ChartResponse>> {
-    Ok(Json(chart_response(&cache, &etf_holdings, &ticker).await?))
+    Ok(Json(chart_response(cache, etf_holdings, &ticker).await?))
+}
+
+/// Handler for the health endpoint, answers as long as the process is up.
+#[get("/health")]
+fn health_handler() -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "ok".to_string(),
+    })
+}
+
+/// Handler for the readiness endpoint, 503 if the server is degraded (see `ready_response`).
+#[get("/ready")]
+#[instrument(skip_all)]
+async fn ready_handler(
+    cache: &State<Cache>,
+    etf_holdings: &State<Arc<ETFHoldings>>,
+) -> (Status, Json<ReadyResponse>) {
+    let response = ready_response(cache, etf_holdings).await;
+    let status = if response.ready {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+    (status, Json(response))
+}
+
+/// Handler for Prometheus metrics, gauges of `ETFHoldings` state are updated when scraped.
+#[get("/metrics")]
+#[instrument(skip_all)]
+async fn metrics_handler(etf_holdings: &State<Arc<ETFHoldings>>) -> GoodResult<String> {
+    for manager in etf_holdings.fund_managers().await {
+        SUPPORTED_ETFS
+            .with_label_values(&[&manager.name])
+            .set(manager.etfs as i64);
+    }
+    let newest_snapshot = etf_holdings
+        .snapshots()
+        .await
+        .iter()
+        .filter_map(|snapshot| last_update_timestamp(&snapshot.last_update))
+        .max();
+    // The gauge is registered on first use, so it's missing until there's a snapshot
+    if let Some(newest_snapshot) = newest_snapshot {
+        NEWEST_SNAPSHOT_TIMESTAMP.set(newest_snapshot as f64);
+    }
+
+    TextEncoder::new()
+        .encode_to_string(&prometheus::gather())
+        .map_err(to_good_error)
+}
+
+/// Create ETFHoldings, with extra ticker mappings if `ETF_TICKER_MAPPING` points to a file.
+///
+/// Holdings files are parsed leniently, a few broken rows shouldn't take down a whole ETF.
+async fn etf_holdings() -> ETFHoldings {
+    let options = ETFHoldingsOptions {
+        ticker_mapping_path: std::env::var_os("ETF_TICKER_MAPPING").map(PathBuf::from),
+        lenient_parsing: true,
+        upstream_observer: Some(Arc::new(UpstreamMetrics)),
+        ..Default::default()
+    };
+    ETFHoldings::with_options(options)
+        .await
+        .unwrap_or_else(|err| panic!("Failed to set up ETFHoldings: {:?}", err))
+}
+
+/// Keep trying to set up fund managers that failed, e.g. because their website was down at startup.
+async fn retry_failed_managers(etf_holdings: Arc<ETFHoldings>) {
+    let mut interval = tokio::time::interval(MANAGER_RETRY_INTERVAL);
+    // The first tick completes right away, the managers were just set up
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        etf_holdings.retry_failed_managers().await;
+    }
 }
 
 /// The entry point of the binary.
 #[launch]
 async fn rocket() -> _ {
+    init_tracing();
+    let etf_holdings = Arc::new(etf_holdings().await);
+    tokio::spawn(retry_failed_managers(etf_holdings.clone()));
     rocket::build()
+        .attach(RequestLogger)
+        .manage(AdminToken::from_env())
         .manage(Cache::new().await)
-        .manage(ETFHoldings::new().await)
+        .manage(etf_holdings)
         .mount(
             "/api",
-            routes![list_handler, chart_handler, details_handler],
+            routes![
+                list_handler,
+                chart_handler,
+                details_handler,
+                breakdown_handler,
+                holdings_csv_handler,
+                exposure_handler,
+                overlap_handler,
+                holding_etfs_handler,
+                portfolio_look_through_handler,
+                unmapped_exchanges_handler,
+                reload_ticker_mapping_handler
+            ],
         )
+        .mount("/", routes![health_handler, ready_handler, metrics_handler])
 }
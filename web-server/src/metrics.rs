@@ -0,0 +1,78 @@
+//! Prometheus metrics, served at `/metrics`.
+
+use etf_holdings_lib::UpstreamObserver;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge, register_histogram_vec, register_int_counter_vec, register_int_gauge_vec,
+    Gauge, HistogramVec, IntCounterVec, IntGaugeVec,
+};
+use std::time::Duration;
+
+lazy_static! {
+    /// Latency of API requests by route
+    pub static ref REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "etf_holdings_request_duration_seconds",
+        "Latency of API requests",
+        &["method", "route", "status"]
+    )
+    .unwrap();
+    /// `Cache` lookups by cache (details, prices) and result (hit, miss)
+    pub static ref CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "etf_holdings_cache_lookups_total",
+        "Cache lookups",
+        &["cache", "result"]
+    )
+    .unwrap();
+    /// Requests to fund managers and Yahoo by host and outcome (success, error)
+    pub static ref UPSTREAM_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "etf_holdings_upstream_requests_total",
+        "Requests to fund managers and price sources",
+        &["host", "outcome"]
+    )
+    .unwrap();
+    /// Latency of requests to fund managers and Yahoo by host
+    pub static ref UPSTREAM_DURATION: HistogramVec = register_histogram_vec!(
+        "etf_holdings_upstream_request_duration_seconds",
+        "Latency of requests to fund managers and price sources",
+        &["host"]
+    )
+    .unwrap();
+    /// Supported ETFs by fund manager, updated when scraped
+    pub static ref SUPPORTED_ETFS: IntGaugeVec = register_int_gauge_vec!(
+        "etf_holdings_supported_etfs",
+        "Supported ETFs per fund manager",
+        &["manager"]
+    )
+    .unwrap();
+    /// Date (Unix time) of the newest fetched holdings snapshot, updated when scraped
+    pub static ref NEWEST_SNAPSHOT_TIMESTAMP: Gauge = register_gauge!(
+        "etf_holdings_newest_snapshot_timestamp_seconds",
+        "Date of the newest fetched holdings snapshot as Unix time"
+    )
+    .unwrap();
+}
+
+/// Record a cache lookup.
+pub fn record_cache_lookup(cache: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    CACHE_LOOKUPS.with_label_values(&[cache, result]).inc();
+}
+
+/// Record a request to a fund manager or Yahoo.
+pub fn record_upstream_request(host: &str, duration: Duration, success: bool) {
+    let outcome = if success { "success" } else { "error" };
+    UPSTREAM_REQUESTS.with_label_values(&[host, outcome]).inc();
+    UPSTREAM_DURATION
+        .with_label_values(&[host])
+        .observe(duration.as_secs_f64());
+}
+
+/// Records the requests `ETFHoldings` makes to fund managers
+#[derive(Debug)]
+pub struct UpstreamMetrics;
+
+impl UpstreamObserver for UpstreamMetrics {
+    fn request_finished(&self, host: &str, duration: Duration, success: bool) {
+        record_upstream_request(host, duration, success);
+    }
+}
@@ -0,0 +1,38 @@
+//! Module used for constructing the overlap response.
+
+use etf_holdings_lib::{overlap, ETFHoldings, Overlap};
+use std::collections::HashSet;
+
+use crate::types::{etf_details_error, GoodError, GoodResult};
+
+/// Overlap response compares the holdings of a comma separated list of ETF tickers.
+pub async fn overlap_response(etf_holdings: &ETFHoldings, tickers: &str) -> GoodResult<Overlap> {
+    let tickers: Vec<String> = tickers
+        .split(',')
+        .map(|ticker| ticker.trim().to_string())
+        .filter(|ticker| !ticker.is_empty())
+        .collect();
+    let mut seen = HashSet::new();
+    if let Some(ticker) = tickers.iter().find(|ticker| !seen.insert(ticker.as_str())) {
+        return Err(GoodError::BadRequest(format!(
+            "{} is listed more than once.",
+            ticker
+        )));
+    }
+    if tickers.len() < 2 {
+        return Err(GoodError::BadRequest(
+            "Overlap needs at least two tickers.".to_string(),
+        ));
+    }
+
+    let mut etfs = Vec::new();
+    for ticker in &tickers {
+        etfs.push(
+            etf_holdings
+                .etf_details(ticker)
+                .await
+                .map_err(|err| etf_details_error(ticker, err))?,
+        );
+    }
+    Ok(overlap(&etfs))
+}
@@ -1,22 +1,48 @@
 //! Contains response types, customer errors, etc.
 
+use etf_holdings_lib::{
+    ETFMetrics, Error as ETFErr, ExposureSummary, FundManagerStatus, ParseWarning, Symbols,
+    ValidationReport,
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
+use tracing::{error, warn};
 
 /// Error type that emits good HTTP status by implementing rocket::response::Responder.
 #[derive(Debug)]
 pub enum GoodError {
     Generic(String),
     NotFound(String),
+    BadRequest(String),
+    /// The request was fine but the server can't carry it out, e.g. because of a broken config file
+    Unprocessable(String),
 }
 
 impl<'r> rocket::response::Responder<'r, 'static> for GoodError {
-    fn respond_to(self, _: &'r rocket::request::Request<'_>) -> rocket::response::Result<'static> {
-        warn_!("Error: {:?}", self);
+    fn respond_to(
+        self,
+        request: &'r rocket::request::Request<'_>,
+    ) -> rocket::response::Result<'static> {
         match self {
-            GoodError::Generic(_) => Err(rocket::http::Status::InternalServerError),
-            GoodError::NotFound(_) => Err(rocket::http::Status::NotFound),
+            GoodError::Generic(msg) => {
+                error!(error = %msg, "Internal server error");
+                Err(rocket::http::Status::InternalServerError)
+            }
+            GoodError::NotFound(msg) => {
+                warn!(error = %msg, "Not found");
+                Err(rocket::http::Status::NotFound)
+            }
+            // The message tells the client what to fix so it goes in the body
+            GoodError::BadRequest(msg) => {
+                warn!(error = %msg, "Bad request");
+                (rocket::http::Status::BadRequest, msg).respond_to(request)
+            }
+            // The operator needs the message to fix the server's config
+            GoodError::Unprocessable(msg) => {
+                error!(error = %msg, "Unprocessable request");
+                (rocket::http::Status::UnprocessableEntity, msg).respond_to(request)
+            }
         }
     }
 }
@@ -28,6 +54,17 @@ pub fn to_good_error<T: Display>(error: T) -> GoodError {
     GoodError::Generic(error.to_string())
 }
 
+/// Convert an error from ETFHoldings::etf_details to GoodError
+pub fn etf_details_error(ticker: &str, error: ETFErr) -> GoodError {
+    match error {
+        ETFErr::NotFound => GoodError::NotFound(format!("Can't find {} in ETFHoldings.", ticker)),
+        ETFErr::Generic(msg) => GoodError::Generic(format!(
+            "Error ETFHoldings::etf_details({}): {:?}",
+            ticker, msg
+        )),
+    }
+}
+
 /// Result with a GoodError to produce good HTTP status
 pub type GoodResult<T> = std::result::Result<T, GoodError>;
 
@@ -58,9 +95,26 @@ pub struct ChartPrice {
 pub struct DetailsResponse {
     pub ticker: String,
     pub name: String,
+    /// Currency of the values in this response (except price histories)
+    pub currency: String,
     pub equity_holdings: Vec<DetailsEquityHolding>,
     pub other_holdings: HashMap<String, f64>,
     pub prices: Option<Vec<HistoricalPrices>>,
+    pub nav: DetailsNav,
+    pub exposure: ExposureSummary,
+    pub metrics: ETFMetrics,
+    pub warnings: Vec<ParseWarning>,
+    pub validation: ValidationReport,
+}
+
+/// Implied NAV from holdings compared with the market price
+#[derive(Serialize, Debug, Clone)]
+pub struct DetailsNav {
+    pub implied_nav: Option<f64>,
+    pub market_price: Option<f64>,
+    pub market_price_timestamp: Option<i64>,
+    /// Premium (positive) or discount (negative) of the market price to the implied NAV in %
+    pub premium_discount: Option<f64>,
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -70,6 +124,7 @@ pub struct DetailsEquityHolding {
     pub weight: f64,
     pub location: String,
     pub exchange: String,
+    pub symbols: Symbols,
     pub prices: Option<Vec<HistoricalPrices>>,
 }
 
@@ -84,3 +139,31 @@ pub struct HistoricalPrices {
     pub close: f64,
     pub adjclose: f64,
 }
+
+/// Response of the health endpoint
+#[derive(Serialize, Debug, Clone)]
+pub struct HealthResponse {
+    pub status: String,
+}
+
+/// Response of the readiness endpoint
+#[derive(Serialize, Debug, Clone)]
+pub struct ReadyResponse {
+    /// False if no ETFs are supported, a fund manager couldn't be set up or most recent Yahoo
+    /// fetches failed
+    pub ready: bool,
+    /// Number of supported ETFs
+    pub etfs: usize,
+    pub fund_managers: Vec<FundManagerStatus>,
+    pub yahoo: YahooStatus,
+}
+
+/// When fetching prices from Yahoo last worked and failed (unix timestamps), and how many fetches
+/// failed in the last `YAHOO_STATUS_WINDOW_SECONDS`. Unknown symbols don't count as failures.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct YahooStatus {
+    pub last_success: Option<i64>,
+    pub last_error: Option<i64>,
+    pub recent_fetches: usize,
+    pub recent_failures: usize,
+}
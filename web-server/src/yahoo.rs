@@ -1,10 +1,20 @@
 //! Yahoo is our source of price history.
 
-use chrono::{NaiveDateTime, Timelike};
+use async_trait::async_trait;
+use chrono::{DateTime, Timelike};
+use etf_holdings_lib::{Error as ETFErr, FxRateSource};
+use reqwest::StatusCode;
 use serde::Deserialize;
+use std::time::Instant;
+use tracing::{debug, instrument};
 
+use crate::cache::Cache;
+use crate::metrics::record_upstream_request;
 use crate::types::{to_good_error, GoodError, GoodResult, HistoricalPrices};
 
+/// Host of the Yahoo price API
+const YAHOO_HOST: &str = "query1.finance.yahoo.com";
+
 // The yahoo response is annoyingly nested so there's gonna be quite a few structs
 
 /// Yahoo price history response type
@@ -52,34 +62,49 @@ pub struct YahooAdjclose {
 // </yahoo response object>
 
 /// Fetch price history for a stock
+#[instrument]
 pub async fn fetch_historical_prices(ticker: &String) -> GoodResult<Vec<HistoricalPrices>> {
     let url = format!(
-        "https://query1.finance.yahoo.com/v8/finance/chart/{}?interval=1d&range=6mo",
-        ticker
+        "https://{}/v8/finance/chart/{}?interval=1d&range=6mo",
+        YAHOO_HOST, ticker
     );
-    let resp: YahooResponse = reqwest::get(url)
-        .await
-        .map_err(to_good_error)?
-        .json()
-        .await
-        .map_err(to_good_error)?;
+    let start = Instant::now();
+    // Recorded once the body is read, errors while reading it count as failed requests too
+    let body: Result<_, reqwest::Error> = async {
+        let response = reqwest::get(url).await?.error_for_status()?;
+        response.bytes().await
+    }
+    .await;
+    record_upstream_request(YAHOO_HOST, start.elapsed(), body.is_ok());
+    let body = body.map_err(|err| match err.status() {
+        // Yahoo answers unknown symbols with 404, 429 means it's rate limiting us
+        Some(status) if status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS => {
+            GoodError::NotFound(format!("Yahoo has no prices for {} ({}).", ticker, status))
+        }
+        _ => to_good_error(err),
+    })?;
+    debug!(
+        duration_ms = start.elapsed().as_millis() as u64,
+        bytes = body.len(),
+        "Fetched prices from Yahoo"
+    );
+    let resp: YahooResponse = serde_json::from_slice(&body).map_err(to_good_error)?;
 
     let result = resp
         .chart
         .result
-        .get(0)
-        .ok_or("Yahoo response had no results.")
-        .map_err(to_good_error)?;
+        .first()
+        .ok_or_else(|| GoodError::NotFound(format!("Yahoo has no prices for {}.", ticker)))?;
     let quote = result
         .indicators
         .quote
-        .get(0)
+        .first()
         .ok_or("Yahoo response had no quotes.")
         .map_err(to_good_error)?;
     let adjclose = result
         .indicators
         .adjclose
-        .get(0)
+        .first()
         .ok_or("Yahoo response had no adjclose.")
         .map_err(to_good_error)?;
     let iter = result
@@ -96,18 +121,20 @@ pub async fn fetch_historical_prices(ticker: &String) -> GoodResult<Vec<Historic
     for ((((((timestamp, volume), open), low), high), close), adjclose) in iter {
         // remove the timezone offset and make all timestamp in GMT then use the
         // midnight timestamp because we don't really care about when the markets close
-        let ts_normalised = NaiveDateTime::from_timestamp(timestamp - result.meta.gmtoffset, 0);
+        let ts_normalised = DateTime::from_timestamp(timestamp - result.meta.gmtoffset, 0)
+            .ok_or("Yahoo timestamp out of range.")
+            .map_err(to_good_error)?;
         let midnight_ts =
             ts_normalised.timestamp() - i64::from(ts_normalised.time().num_seconds_from_midnight());
 
         history.push(HistoricalPrices {
             timestamp: midnight_ts,
-            volume: volume.clone(),
-            open: open.clone(),
-            low: low.clone(),
-            high: high.clone(),
-            close: close.clone(),
-            adjclose: adjclose.clone(),
+            volume: *volume,
+            open: *open,
+            low: *low,
+            high: *high,
+            close: *close,
+            adjclose: *adjclose,
         })
     }
 
@@ -122,3 +149,23 @@ pub async fn fetch_historical_prices(ticker: &String) -> GoodResult<Vec<Historic
 
     Ok(history)
 }
+
+/// Latest exchange rates from Yahoo, e.g. the `USDAUD=X` symbol for USD to AUD (see
+/// `Cache::fx_rate`)
+pub struct YahooFxRates<'a> {
+    pub cache: &'a Cache,
+}
+
+#[async_trait]
+impl FxRateSource for YahooFxRates<'_> {
+    async fn rate(&self, from: &str, to: &str) -> Result<f64, ETFErr> {
+        if from == to {
+            return Ok(1.0);
+        }
+        let symbol = format!("{}{}=X", from, to);
+        self.cache
+            .fx_rate(&symbol)
+            .await
+            .map_err(|err| ETFErr::from(format!("{:?}", err)))
+    }
+}